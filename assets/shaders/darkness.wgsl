@@ -0,0 +1,81 @@
+// Darkness mask drawn over the world. Every light cuts a soft hole into it,
+// tinting the edge of the hole with its colour.
+
+struct Light {
+    color: vec4<f32>,
+    position: vec2<f32>,
+    direction: vec2<f32>,
+    radius: f32,
+    // Cosine of half the cone angle, -1 for point lights.
+    cos_half_angle: f32,
+    intensity: f32,
+};
+
+struct Darkness {
+    color: vec4<f32>,
+    count: u32,
+    // Length must match `MAX_LIGHTS` in `src/lighting.rs`.
+    lights: array<Light, 16>,
+    // 128 rays per light, counter-clockwise from +x, packed four to a vec4.
+    // Length must be `MAX_LIGHTS * SHADOW_RAYS / 4`.
+    reach: array<vec4<f32>, 512>,
+};
+
+@group(1) @binding(0)
+var<uniform> darkness: Darkness;
+
+let SHADOW_RAYS: u32 = 128u;
+let SHADOW_SOFTNESS: f32 = 2.0;
+let TAU: f32 = 6.28318530718;
+
+fn ray_reach(light: u32, ray: u32) -> f32 {
+    let index = light * SHADOW_RAYS + ray % SHADOW_RAYS;
+    return darkness.reach[index / 4u][index % 4u];
+}
+
+// Distance the light travels towards `offset` before an occluder stops it.
+fn reach_towards(light: u32, offset: vec2<f32>) -> f32 {
+    var angle = atan2(offset.y, offset.x);
+    if (angle < 0.0) {
+        angle = angle + TAU;
+    }
+    let ray = angle / TAU * f32(SHADOW_RAYS);
+    let first = u32(floor(ray)) % SHADOW_RAYS;
+    return mix(ray_reach(light, first), ray_reach(light, first + 1u), fract(ray));
+}
+
+struct FragmentInput {
+    #import bevy_sprite::mesh2d_vertex_output
+};
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    // Overlapping lights do not add up, the brightest one wins. Colours are blended by strength.
+    var light = 0.0;
+    var total = 0.0;
+    var tint = vec3<f32>(0.0);
+
+    for (var i = 0u; i < darkness.count; i = i + 1u) {
+        let source = darkness.lights[i];
+        let offset = in.world_position.xy - source.position;
+        let distance = length(offset);
+
+        var amount = clamp(1.0 - distance / source.radius, 0.0, 1.0);
+        if (source.cos_half_angle > -1.0 && distance > 0.0) {
+            let cos_angle = dot(offset / distance, source.direction);
+            let edge = mix(source.cos_half_angle, 1.0, 0.2);
+            amount = amount * smoothstep(source.cos_half_angle, edge, cos_angle);
+        }
+        amount = amount * (1.0 - smoothstep(0.0, SHADOW_SOFTNESS, distance - reach_towards(i, offset)));
+        amount = amount * source.intensity;
+
+        light = max(light, amount);
+        total = total + amount;
+        tint = tint + source.color.rgb * amount;
+    }
+
+    let lit = clamp(light, 0.0, 1.0);
+    let hue = tint / max(total, 0.0001);
+
+    return vec4<f32>(mix(darkness.color.rgb, hue, lit), darkness.color.a * (1.0 - lit));
+}
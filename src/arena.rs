@@ -0,0 +1,533 @@
+//! The tile world the round is played in: a floor, walls around and across it, and props.
+//!
+//! The built-in arena is used until a level has been loaded, see `level`. Bounded arenas are spawned
+//! whole; the endless world of `cave` is streamed in chunks around the player.
+
+use std::collections::HashSet;
+
+use bevy::{prelude::*, sprite::Anchor};
+use bevy_ecs_tilemap::prelude::*;
+use bevy_rapier2d::prelude::{Collider, RigidBody, SolverGroups};
+use rand::prelude::*;
+
+use crate::{
+    cave::Cave,
+    character::{player::PlayerPosition, Position},
+    lighting::Occluder,
+    physics::WORLD_GROUP,
+    rendering,
+};
+
+/// Width and height of a tile in world units.
+pub const TILE_SIZE: f32 = 8.;
+
+/// Width and height of the arena in tiles. The middle of it is the world origin.
+const ARENA_SIZE: usize = 64;
+
+/// Walls across the arena, as first tile, length in tiles and whether they run vertically.
+const WALLS: [((usize, usize), usize, bool); 6] = [
+    ((8, 14), 10, false),
+    ((46, 12), 10, false),
+    ((36, 6), 8, false),
+    ((20, 52), 12, false),
+    ((14, 40), 8, true),
+    ((50, 38), 12, true),
+];
+
+/// Tiles around the middle of the arena kept free, so the player never starts boxed in.
+const CLEAR_RADIUS: i32 = 6;
+
+/// Floor textures and how often each is picked, out of 16.
+const FLOOR_TEXTURES: [(u32, u32); 5] = [(0, 9), (1, 2), (2, 2), (6, 1), (7, 2)];
+
+/// Below everything else, which `rendering::OrderedZ` keeps within `ORDERED_Z_RANGE` of zero.
+const FLOOR_Z: f32 = -10_000.;
+
+/// Width and height of a chunk of the endless world in tiles.
+const CHUNK_SIZE: i32 = 16;
+
+/// Chunks are loaded this many chunks around the player's, and unloaded when further than
+/// `UNLOAD_RADIUS`, so walking back and forth over a chunk border does not reload them.
+const LOAD_RADIUS: i32 = 2;
+const UNLOAD_RADIUS: i32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tile {
+    Floor,
+    Wall,
+    Bush,
+    Stone,
+}
+
+impl Tile {
+    /// Blocks movement.
+    pub fn is_solid(self) -> bool {
+        matches!(self, Tile::Wall | Tile::Stone)
+    }
+
+    /// Blocks light.
+    pub fn casts_shadow(self) -> bool {
+        self == Tile::Wall
+    }
+
+    /// The tile drawn with texture `index` of `tiles.png`. Floor textures are all `Floor`.
+    pub fn from_texture(index: u32) -> Self {
+        match index {
+            3 => Tile::Wall,
+            4 => Tile::Bush,
+            5 => Tile::Stone,
+            _ => Tile::Floor,
+        }
+    }
+
+    /// Index in `tiles.png` of what stands on the floor of this tile, if anything.
+    fn standing_texture(self) -> Option<usize> {
+        match self {
+            Tile::Floor => None,
+            Tile::Wall => Some(3),
+            Tile::Bush => Some(4),
+            Tile::Stone => Some(5),
+        }
+    }
+}
+
+/// A cheap, stable hash of a tile position, used to vary floors and place props.
+pub fn tile_hash(x: i32, y: i32, seed: u32) -> u32 {
+    let mut hash = (x as u32)
+        .wrapping_mul(0x9e37_79b1)
+        .wrapping_add((y as u32).wrapping_mul(0x85eb_ca6b))
+        ^ seed;
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x2c1b_3c6d);
+    hash ^= hash >> 12;
+    hash = hash.wrapping_mul(0x297a_2d39);
+    hash ^ (hash >> 15)
+}
+
+/// An area enemies may spawn in.
+#[derive(Clone, Copy, Debug)]
+pub struct SpawnZone {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl SpawnZone {
+    pub fn contains(&self, point: Vec2) -> bool {
+        point.cmpge(self.min).all() && point.cmple(self.max).all()
+    }
+
+    pub fn random_point<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec2 {
+        Vec2::new(
+            rng.gen_range(self.min.x..=self.max.x),
+            rng.gen_range(self.min.y..=self.max.y),
+        )
+    }
+}
+
+/// The tiles of the world, row by row from the bottom left corner at `origin`.
+#[derive(Clone)]
+pub struct Arena {
+    pub width: usize,
+    pub height: usize,
+    pub origin: Vec2,
+    tiles: Vec<Tile>,
+    /// Texture of the floor under every tile.
+    floor: Vec<u32>,
+    /// Where the player starts a round.
+    pub player_start: Vec2,
+    /// Where enemies spawn. Anywhere out of sight when empty.
+    pub spawn_zones: Vec<SpawnZone>,
+    /// Generates tiles everywhere instead, when set. The grid is empty then.
+    pub endless: Option<Cave>,
+}
+
+impl Arena {
+    /// An open floor of `width` by `height` tiles, with varied floor textures.
+    pub fn new(width: usize, height: usize, origin: Vec2) -> Self {
+        let floor = (0..width * height)
+            .map(|i| floor_texture(i % width, i / width))
+            .collect();
+        Arena {
+            width,
+            height,
+            origin,
+            tiles: vec![Tile::Floor; width * height],
+            floor,
+            player_start: Vec2::ZERO,
+            spawn_zones: Vec::new(),
+            endless: None,
+        }
+    }
+
+    /// The endless world of `cave`, with the player starting at the origin.
+    pub fn endless(cave: Cave) -> Self {
+        Arena {
+            endless: Some(cave),
+            ..Arena::new(0, 0, Vec2::ZERO)
+        }
+    }
+
+    /// The built-in arena: walled in, with a few walls across it and props scattered around.
+    pub fn generate() -> Self {
+        let half = ARENA_SIZE as f32 * TILE_SIZE / 2.;
+        let mut arena = Arena::new(ARENA_SIZE, ARENA_SIZE, Vec2::splat(-half));
+
+        for i in 0..ARENA_SIZE {
+            arena.set(i, 0, Tile::Wall);
+            arena.set(i, ARENA_SIZE - 1, Tile::Wall);
+            arena.set(0, i, Tile::Wall);
+            arena.set(ARENA_SIZE - 1, i, Tile::Wall);
+        }
+        for ((x, y), length, vertical) in WALLS {
+            for i in 0..length {
+                if vertical {
+                    arena.set(x, y + i, Tile::Wall);
+                } else {
+                    arena.set(x + i, y, Tile::Wall);
+                }
+            }
+        }
+
+        let middle = ARENA_SIZE as i32 / 2;
+        for y in 0..ARENA_SIZE {
+            for x in 0..ARENA_SIZE {
+                let (dx, dy) = (x as i32 - middle, y as i32 - middle);
+                if arena.tile(x as i32, y as i32) != Some(Tile::Floor)
+                    || dx * dx + dy * dy < CLEAR_RADIUS * CLEAR_RADIUS
+                {
+                    continue;
+                }
+                match tile_hash(x as i32, y as i32, 0) % 100 {
+                    0..=1 => arena.set(x, y, Tile::Bush),
+                    2 => arena.set(x, y, Tile::Stone),
+                    _ => (),
+                }
+            }
+        }
+        arena
+    }
+
+    /// The tile at `x`, `y`, or `None` outside the arena.
+    pub fn tile(&self, x: i32, y: i32) -> Option<Tile> {
+        if let Some(cave) = &self.endless {
+            return Some(cave.tile(x, y));
+        }
+        self.index(x, y).map(|index| self.tiles[index])
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        let (x, y) = (usize::try_from(x).ok()?, usize::try_from(y).ok()?);
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(y * self.width + x)
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, tile: Tile) {
+        if x < self.width && y < self.height {
+            self.tiles[y * self.width + x] = tile;
+        }
+    }
+
+    pub fn floor_texture(&self, x: i32, y: i32) -> u32 {
+        if let Some(cave) = &self.endless {
+            return cave.floor_texture(x, y);
+        }
+        self.index(x, y).map_or(0, |index| self.floor[index])
+    }
+
+    pub fn set_floor_texture(&mut self, x: usize, y: usize, texture: u32) {
+        if x < self.width && y < self.height {
+            self.floor[y * self.width + x] = texture;
+        }
+    }
+
+    /// Centre of the tile at `x`, `y` in world units.
+    pub fn tile_center(&self, x: i32, y: i32) -> Vec2 {
+        self.origin + (Vec2::new(x as f32, y as f32) + 0.5) * TILE_SIZE
+    }
+
+    /// Coordinates of the tile under `point`.
+    pub fn tile_coords(&self, point: Vec2) -> IVec2 {
+        ((point - self.origin) / TILE_SIZE).floor().as_ivec2()
+    }
+
+    /// The tile under `point`, or `None` outside the arena.
+    pub fn tile_at(&self, point: Vec2) -> Option<Tile> {
+        let tile = self.tile_coords(point);
+        self.tile(tile.x, tile.y)
+    }
+
+    /// Whether something can stand at `point`: inside the arena and not in a solid tile.
+    pub fn is_open(&self, point: Vec2) -> bool {
+        self.tile_at(point).map_or(false, |tile| !tile.is_solid())
+    }
+}
+
+/// Something standing on a tile, positioned at the bottom edge of the tile so it sorts with characters.
+#[derive(Component)]
+pub struct ArenaTile(pub Tile);
+
+/// A rectangle of tiles with its colliders, walls and props spawned as children.
+#[derive(Component)]
+pub struct ArenaRegion {
+    /// Coordinates of the bottom left tile.
+    pub min: IVec2,
+    pub size: UVec2,
+}
+
+pub struct ArenaPlugin;
+
+impl Plugin for ArenaPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Arena::generate())
+            .add_system(rebuild_arena)
+            .add_system(stream_chunks.after(rebuild_arena));
+    }
+}
+
+/// The floor tilemaps and the sprites of walls and props.
+pub struct ArenaSpritePlugin;
+
+impl Plugin for ArenaSpritePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(TilemapPlugin)
+            .add_startup_system(load_tileset)
+            .add_system_to_stage(CoreStage::PostUpdate, insert_floor)
+            .add_system_to_stage(CoreStage::PostUpdate, insert_sprite);
+    }
+}
+
+struct TileAtlas {
+    texture: Handle<Image>,
+    atlas: Handle<TextureAtlas>,
+}
+
+/// Replaces everything spawned from the arena when it changes, e.g. when a level is loaded.
+fn rebuild_arena(
+    mut commands: Commands,
+    arena: Res<Arena>,
+    regions: Query<Entity, With<ArenaRegion>>,
+) {
+    if !arena.is_changed() {
+        return;
+    }
+
+    for entity in &regions {
+        commands.entity(entity).despawn_recursive();
+    }
+    if arena.endless.is_none() {
+        spawn_region(
+            &mut commands,
+            &arena,
+            IVec2::ZERO,
+            UVec2::new(arena.width as u32, arena.height as u32),
+        );
+    }
+}
+
+/// Loads the chunks of an endless arena around the player and unloads those left behind.
+///
+/// Follows the gameplay position, as the chunks bring colliders with them.
+fn stream_chunks(
+    mut commands: Commands,
+    arena: Res<Arena>,
+    player: Query<&Position, With<PlayerPosition>>,
+    regions: Query<(Entity, &ArenaRegion)>,
+) {
+    // A changed arena has just been cleared, and is streamed again from the next frame
+    if arena.endless.is_none() || arena.is_changed() {
+        return;
+    }
+    let player = match player.get_single() {
+        Ok(position) => position.current,
+        Err(_) => return,
+    };
+    let tile = arena.tile_coords(player);
+    let center = IVec2::new(tile.x.div_euclid(CHUNK_SIZE), tile.y.div_euclid(CHUNK_SIZE));
+
+    let mut loaded = HashSet::new();
+    for (entity, region) in &regions {
+        // Chunks start at multiples of the chunk size, so this divides exactly
+        let chunk = region.min / CHUNK_SIZE;
+        if (chunk - center).abs().max_element() > UNLOAD_RADIUS {
+            commands.entity(entity).despawn_recursive();
+        } else {
+            loaded.insert(chunk);
+        }
+    }
+    for y in -LOAD_RADIUS..=LOAD_RADIUS {
+        for x in -LOAD_RADIUS..=LOAD_RADIUS {
+            let chunk = center + IVec2::new(x, y);
+            if !loaded.contains(&chunk) {
+                spawn_region(
+                    &mut commands,
+                    &arena,
+                    chunk * CHUNK_SIZE,
+                    UVec2::splat(CHUNK_SIZE as u32),
+                );
+            }
+        }
+    }
+}
+
+fn spawn_region(commands: &mut Commands, arena: &Arena, min: IVec2, size: UVec2) {
+    let max = min + size.as_ivec2();
+    commands
+        .spawn_bundle(SpatialBundle::default())
+        .insert(Name::new(format!("Tiles {} {}", min.x, min.y)))
+        .insert(ArenaRegion { min, size })
+        .with_children(|parent| {
+            for y in min.y..max.y {
+                // One collider per run of solid tiles of the same kind along the row
+                let mut x = min.x;
+                while x < max.x {
+                    let tile = arena.tile(x, y).unwrap_or(Tile::Floor);
+                    let start = x;
+                    while x < max.x && arena.tile(x, y) == Some(tile) {
+                        x += 1;
+                    }
+                    if tile.is_solid() {
+                        spawn_collider(parent, arena, tile, y, start..x);
+                    }
+                }
+
+                for x in min.x..max.x {
+                    let tile = arena.tile(x, y).unwrap_or(Tile::Floor);
+                    if tile.standing_texture().is_none() {
+                        continue;
+                    }
+                    let position = arena.tile_center(x, y) - Vec2::Y * TILE_SIZE / 2.;
+                    parent
+                        .spawn_bundle(SpatialBundle {
+                            transform: Transform::from_translation(position.extend(0.)),
+                            ..default()
+                        })
+                        .insert(ArenaTile(tile))
+                        .insert(rendering::OrderedZ);
+                }
+            }
+        });
+}
+
+fn spawn_collider(
+    parent: &mut ChildBuilder,
+    arena: &Arena,
+    tile: Tile,
+    y: i32,
+    run: std::ops::Range<i32>,
+) {
+    let center = (arena.tile_center(run.start, y) + arena.tile_center(run.end - 1, y)) / 2.;
+    let mut collider = parent.spawn_bundle(SpatialBundle {
+        transform: Transform::from_translation(center.extend(0.)),
+        ..default()
+    });
+    collider
+        .insert(Name::new(format!("{:?}", tile)))
+        .insert(RigidBody::Fixed)
+        .insert(Collider::cuboid(
+            run.len() as f32 * TILE_SIZE / 2.,
+            TILE_SIZE / 2.,
+        ))
+        .insert(SolverGroups::new(WORLD_GROUP, u32::MAX));
+    if tile.casts_shadow() {
+        collider.insert(Occluder);
+    }
+}
+
+fn floor_texture(x: usize, y: usize) -> u32 {
+    let mut pick = tile_hash(x as i32, y as i32, 1) % 16;
+    for (texture, weight) in FLOOR_TEXTURES {
+        if pick < weight {
+            return texture;
+        }
+        pick -= weight;
+    }
+    0
+}
+
+fn load_tileset(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+) {
+    let texture: Handle<Image> = asset_server.load("tiles.png");
+    let atlas = TextureAtlas::from_grid(texture.clone(), Vec2::splat(TILE_SIZE), 8, 1);
+    commands.insert_resource(TileAtlas {
+        texture,
+        atlas: texture_atlases.add(atlas),
+    });
+}
+
+/// Gives every region a floor tilemap, as a child so it goes away with the region.
+fn insert_floor(
+    mut commands: Commands,
+    arena: Res<Arena>,
+    tileset: Res<TileAtlas>,
+    regions: Query<(Entity, &ArenaRegion), Added<ArenaRegion>>,
+) {
+    for (entity, region) in &regions {
+        let size = TilemapSize {
+            x: region.size.x,
+            y: region.size.y,
+        };
+        let tilemap = commands.spawn().id();
+        let mut storage = TileStorage::empty(size);
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let position = TilePos { x, y };
+                let coords = region.min + UVec2::new(x, y).as_ivec2();
+                let tile = commands
+                    .spawn()
+                    .insert_bundle(TileBundle {
+                        position,
+                        tilemap_id: TilemapId(tilemap),
+                        texture: TileTexture(arena.floor_texture(coords.x, coords.y)),
+                        ..default()
+                    })
+                    .id();
+                storage.set(&position, Some(tile));
+                commands.entity(tilemap).add_child(tile);
+            }
+        }
+
+        let tile_size = TilemapTileSize {
+            x: TILE_SIZE,
+            y: TILE_SIZE,
+        };
+        // Tiles are drawn centred on their grid position
+        let corner = arena.tile_center(region.min.x, region.min.y);
+        commands
+            .entity(tilemap)
+            .insert_bundle(TilemapBundle {
+                grid_size: tile_size.into(),
+                size,
+                storage,
+                texture: TilemapTexture(tileset.texture.clone()),
+                tile_size,
+                transform: Transform::from_translation(corner.extend(FLOOR_Z)),
+                ..default()
+            })
+            .insert(Name::new("Floor"));
+        commands.entity(entity).add_child(tilemap);
+    }
+}
+
+fn insert_sprite(
+    mut commands: Commands,
+    query: Query<(Entity, &ArenaTile), Added<ArenaTile>>,
+    tileset: Res<TileAtlas>,
+) {
+    for (entity, tile) in &query {
+        if let Some(index) = tile.0.standing_texture() {
+            commands
+                .entity(entity)
+                .insert(TextureAtlasSprite {
+                    index,
+                    anchor: Anchor::BottomCenter,
+                    ..default()
+                })
+                .insert(tileset.atlas.clone());
+        }
+    }
+}
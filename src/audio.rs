@@ -0,0 +1,205 @@
+//! Music, sound effects and ambience on separate `bevy_kira_audio` channels, each with its own volume.
+//!
+//! Audio that fails to load is reported once and then left out, so the game plays on in silence.
+//! The soundtrack itself is mixed in `music`.
+
+use std::{marker::PhantomData, time::Duration};
+
+use bevy::{app::AppExit, asset::LoadState, prelude::*};
+use bevy_egui::egui;
+use bevy_kira_audio::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{game::GameState, storage};
+
+const STORAGE_KEY: &str = "audio";
+
+/// Seconds over which channels fade between states.
+pub const FADE: f32 = 1.5;
+/// Music and ambience are turned down to this fraction while paused.
+const PAUSED_VOLUME: f64 = 0.3;
+
+pub struct MusicChannel;
+pub struct SfxChannel;
+pub struct AmbienceChannel;
+
+/// Volume of every channel from 0 to 1, kept between sessions.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    pub music: f64,
+    pub sfx: f64,
+    pub ambience: f64,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            music: 0.75,
+            sfx: 0.8,
+            ambience: 0.5,
+        }
+    }
+}
+
+impl AudioSettings {
+    /// Sliders for every channel, returning whether one was moved.
+    pub fn show(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        for (name, volume) in [
+            ("music", &mut self.music),
+            ("sfx", &mut self.sfx),
+            ("ambience", &mut self.ambience),
+        ] {
+            changed |= ui
+                .add(egui::Slider::new(volume, 0.0..=1.0).text(name))
+                .changed();
+        }
+        changed
+    }
+
+    /// Volume of the music in `state`. Set on every stem rather than the channel, see `music`.
+    pub fn music_volume(&self, state: &GameState) -> f64 {
+        self.music * ducking(state)
+    }
+}
+
+fn ducking(state: &GameState) -> f64 {
+    if state == &GameState::Paused {
+        PAUSED_VOLUME
+    } else {
+        1.
+    }
+}
+
+/// Audio sources by path, so missing files can be reported and skipped.
+#[derive(Default)]
+pub struct AudioLibrary {
+    /// Sources that have neither loaded nor failed yet.
+    pending: Vec<(String, Handle<AudioSource>)>,
+}
+
+impl AudioLibrary {
+    pub fn load(&mut self, asset_server: &AssetServer, path: &str) -> Handle<AudioSource> {
+        let handle = asset_server.load(path);
+        self.pending.push((path.to_owned(), handle.clone()));
+        handle
+    }
+
+    /// Whether `source` can be played now. Sources still loading or missing are not.
+    pub fn is_ready(asset_server: &AssetServer, source: &Handle<AudioSource>) -> bool {
+        asset_server.get_load_state(source) == LoadState::Loaded
+    }
+
+    /// Whether `source` has neither loaded nor failed yet.
+    pub fn is_loading(&self, source: &Handle<AudioSource>) -> bool {
+        self.pending.iter().any(|(_, pending)| pending == source)
+    }
+}
+
+/// A track looping on channel `T` while `wanted`, started once its source has loaded.
+pub struct LoopingTrack<T> {
+    pub source: Handle<AudioSource>,
+    pub wanted: bool,
+    playing: bool,
+    channel: PhantomData<T>,
+}
+
+impl<T> LoopingTrack<T> {
+    pub fn new(source: Handle<AudioSource>) -> Self {
+        LoopingTrack {
+            source,
+            wanted: true,
+            playing: false,
+            channel: PhantomData,
+        }
+    }
+}
+
+pub struct AudioManagerPlugin;
+
+impl Plugin for AudioManagerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_audio_channel::<MusicChannel>()
+            .add_audio_channel::<SfxChannel>()
+            .add_audio_channel::<AmbienceChannel>()
+            .insert_resource(storage::load::<AudioSettings>(STORAGE_KEY).unwrap_or_default())
+            .init_resource::<AudioLibrary>()
+            .add_startup_system(setup)
+            .add_system(report_missing_audio)
+            .add_system(set_channel_volumes)
+            .add_system_to_stage(CoreStage::Last, save_audio_settings)
+            .add_system(play_looping_track::<AmbienceChannel>);
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut library: ResMut<AudioLibrary>,
+) {
+    commands.insert_resource(LoopingTrack::<AmbienceChannel>::new(
+        library.load(&asset_server, "ambience.wav"),
+    ));
+}
+
+fn report_missing_audio(asset_server: Res<AssetServer>, mut library: ResMut<AudioLibrary>) {
+    library
+        .pending
+        .retain(|(path, handle)| match asset_server.get_load_state(handle) {
+            LoadState::Failed => {
+                warn!("Could not load audio {}, playing without it", path);
+                false
+            }
+            LoadState::Loaded | LoadState::Unloaded => false,
+            LoadState::NotLoaded | LoadState::Loading => true,
+        });
+}
+
+fn play_looping_track<T: Resource>(
+    mut track: ResMut<LoopingTrack<T>>,
+    channel: Res<AudioChannel<T>>,
+    asset_server: Res<AssetServer>,
+) {
+    let fade = AudioTween::linear(Duration::from_secs_f32(FADE));
+    if track.wanted && !track.playing && AudioLibrary::is_ready(&asset_server, &track.source) {
+        channel.play(track.source.clone()).looped().fade_in(fade);
+        track.playing = true;
+    } else if !track.wanted && track.playing {
+        channel.stop().fade_out(fade);
+        track.playing = false;
+    }
+}
+
+/// The music channel is left alone, its volume would override the volumes of the stems.
+fn set_channel_volumes(
+    state: Res<State<GameState>>,
+    settings: Res<AudioSettings>,
+    sfx: Res<AudioChannel<SfxChannel>>,
+    ambience: Res<AudioChannel<AmbienceChannel>>,
+) {
+    if !state.is_changed() && !settings.is_changed() {
+        return;
+    }
+
+    ambience
+        .set_volume(settings.ambience * ducking(state.current()))
+        .fade_in(AudioTween::linear(Duration::from_secs_f32(FADE)));
+    sfx.set_volume(settings.sfx);
+}
+
+/// Saves the volumes when the pause menu is left or the game quits, not on every slider step.
+fn save_audio_settings(
+    state: Res<State<GameState>>,
+    settings: Res<AudioSettings>,
+    mut exit: EventReader<AppExit>,
+    mut saved: Local<Option<AudioSettings>>,
+) {
+    let saved = saved.get_or_insert_with(|| settings.clone());
+    let closed = state.is_changed() && state.current() != &GameState::Paused;
+    let quit = exit.iter().count() > 0;
+    if (closed || quit) && *saved != *settings {
+        storage::save(STORAGE_KEY, &*settings);
+        *saved = settings.clone();
+    }
+}
@@ -1,7 +1,12 @@
 pub mod follow_camera {
     use bevy::{prelude::*, render::camera::ScalingMode};
 
-    use crate::character::player::PlayerPosition;
+    use crate::{
+        character::player::PlayerPosition,
+        config::GameConfig,
+        events::PlayerDied,
+        game::{GameState, RestartGame},
+    };
 
     fn follow_camera_system(
         player: Query<&PlayerPosition>,
@@ -19,17 +24,50 @@ pub mod follow_camera {
     impl Plugin for FollowCameraPlugin {
         fn build(&self, app: &mut App) {
             app.add_startup_system(setup)
-                .add_system_to_stage(CoreStage::Last, follow_camera_system);
+                .add_system_to_stage(CoreStage::Last, follow_camera_system)
+                .add_system(reset_camera)
+                .add_system(zoom_on_death);
         }
     }
 
-    fn setup(mut commands: Commands) {
+    fn setup(mut commands: Commands, config: Res<GameConfig>) {
         commands.spawn_bundle({
             let mut camera = Camera2dBundle::default();
             camera.projection.scaling_mode = ScalingMode::WindowSize;
-            camera.projection.scale = 0.15;
+            camera.projection.scale = config.camera_scale;
             camera.projection.far = f32::MAX;
             camera
         });
     }
+
+    fn zoom_on_death(
+        mut died: EventReader<PlayerDied>,
+        config: Res<GameConfig>,
+        mut camera: Query<&mut OrthographicProjection, With<Camera2d>>,
+    ) {
+        if died.iter().count() == 0 {
+            return;
+        }
+
+        for mut projection in &mut camera {
+            projection.scale = config.game_over_camera_scale;
+        }
+    }
+
+    fn reset_camera(
+        mut events: EventReader<RestartGame>,
+        config: Res<GameConfig>,
+        state: Res<State<GameState>>,
+        mut camera: Query<&mut OrthographicProjection, With<Camera2d>>,
+    ) {
+        let restarted = events.iter().count() > 0;
+        let reloaded = config.is_changed() && state.current() != &GameState::GameOver;
+        if !restarted && !reloaded {
+            return;
+        }
+
+        for mut projection in &mut camera {
+            projection.scale = config.camera_scale;
+        }
+    }
 }
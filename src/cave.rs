@@ -0,0 +1,126 @@
+//! The endless world: rock caves opening into forest clearings, the same for every run with a seed.
+
+use bevy::prelude::*;
+
+use crate::arena::{tile_hash, Tile};
+
+/// Tiles around the origin kept free, so the player never starts inside rock.
+const CLEAR_RADIUS: f32 = 8.;
+
+/// Tiles across the smallest cave features and across whole biomes.
+const CAVE_SCALE: f32 = 5.;
+const TUNNEL_SCALE: f32 = 12.;
+const BIOME_SCALE: f32 = 40.;
+
+/// Noise above which a tile is rock, in caves and in forests.
+const CAVE_WALL: f32 = 0.6;
+const FOREST_WALL: f32 = 0.75;
+
+/// Biome noise above which the world turns to forest.
+const FOREST: f32 = 0.55;
+
+/// Seeded generator of the endless world. Any tile can be asked for in any order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cave {
+    seed: u32,
+}
+
+impl Cave {
+    pub fn new(seed: u64) -> Self {
+        Cave {
+            seed: (seed ^ (seed >> 32)) as u32,
+        }
+    }
+
+    pub fn tile(&self, x: i32, y: i32) -> Tile {
+        let point = Vec2::new(x as f32, y as f32);
+        if point.length() < CLEAR_RADIUS {
+            return Tile::Floor;
+        }
+
+        let forest = self.is_forest(x, y);
+        let rock =
+            self.noise(point / TUNNEL_SCALE, 1) * 0.65 + self.noise(point / CAVE_SCALE, 2) * 0.35;
+        if rock > if forest { FOREST_WALL } else { CAVE_WALL } {
+            return Tile::Wall;
+        }
+
+        let (bushes, stones) = if forest { (8, 1) } else { (1, 3) };
+        match tile_hash(x, y, self.seed.wrapping_add(3)) % 100 {
+            roll if roll < bushes => Tile::Bush,
+            roll if roll < bushes + stones => Tile::Stone,
+            _ => Tile::Floor,
+        }
+    }
+
+    pub fn floor_texture(&self, x: i32, y: i32) -> u32 {
+        let roll = tile_hash(x, y, self.seed.wrapping_add(4)) % 16;
+        if self.is_forest(x, y) {
+            // Mostly grass
+            [7, 7, 7, 7, 7, 7, 7, 0, 0, 0, 0, 0, 1, 2, 6, 7][roll as usize]
+        } else {
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 2, 2, 2, 6][roll as usize]
+        }
+    }
+
+    fn is_forest(&self, x: i32, y: i32) -> bool {
+        self.noise(Vec2::new(x as f32, y as f32) / BIOME_SCALE, 5) > FOREST
+    }
+
+    /// Smooth value noise from 0 to 1, with one random value per whole coordinate.
+    fn noise(&self, point: Vec2, layer: u32) -> f32 {
+        let seed = self.seed.wrapping_add(layer.wrapping_mul(0x68e3_1da4));
+        let corner = point.floor();
+        let (x, y) = (corner.x as i32, corner.y as i32);
+        let value = |dx: i32, dy: i32| (tile_hash(x + dx, y + dy, seed) & 0xffff) as f32 / 65535.;
+        let t = point - corner;
+        let t = t * t * (Vec2::splat(3.) - 2. * t);
+
+        let bottom = value(0, 0) + (value(1, 0) - value(0, 0)) * t.x;
+        let top = value(0, 1) + (value(1, 1) - value(0, 1)) * t.x;
+        bottom + (top - bottom) * t.y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_depend_only_on_seed_and_coordinates() {
+        let cave = Cave::new(12345);
+        let tiles: Vec<Tile> = (-40..40).map(|x| cave.tile(x, x * 3 - 7)).collect();
+
+        // In reverse, so nothing depends on the order tiles are asked for
+        let again: Vec<Tile> = (-40..40)
+            .rev()
+            .map(|x| Cave::new(12345).tile(x, x * 3 - 7))
+            .collect();
+        assert_eq!(again.into_iter().rev().collect::<Vec<_>>(), tiles);
+    }
+
+    #[test]
+    fn different_seeds_make_different_caves() {
+        let tiles = |seed| -> Vec<Tile> {
+            let cave = Cave::new(seed);
+            (0..64).map(|x| cave.tile(x, 20)).collect()
+        };
+
+        assert_ne!(tiles(1), tiles(2));
+    }
+
+    #[test]
+    fn start_is_clear() {
+        let radius = CLEAR_RADIUS as i32;
+        for seed in 0..20 {
+            let cave = Cave::new(seed);
+            for y in -radius..=radius {
+                for x in -radius..=radius {
+                    if Vec2::new(x as f32, y as f32).length() < CLEAR_RADIUS {
+                        assert_eq!(cave.tile(x, y), Tile::Floor, "seed {} at {} {}", seed, x, y);
+                    }
+                }
+            }
+        }
+    }
+}
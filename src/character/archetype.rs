@@ -0,0 +1,697 @@
+use std::ops::Range;
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    ecs::system::EntityCommands,
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use bevy_rapier2d::prelude::Collider;
+use rand::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    config::GameConfig,
+    events::{EnemySpawned, LanternToggled},
+    game::{FixedUpdateStage, GameState, FIXED_TIMESTEP},
+    lighting::LightField,
+    rng::GameRng,
+};
+
+use super::{
+    enemy::{spawn_enemy, Enemy},
+    player::{Lantern, PlayerPosition},
+    steering::{Steer, Steering},
+    MoveDirection, Position, Speed,
+};
+
+/// One kind of enemy, loaded from `assets/enemies.archetypes.ron`.
+#[derive(Clone, Deserialize)]
+pub struct EnemyArchetype {
+    pub name: String,
+    /// Sprite sheet of 16x16 frames in a single row.
+    pub atlas: String,
+    pub frames: usize,
+    /// First and last frame of the walk cycle.
+    pub stalk: (usize, usize),
+    /// Frames shown while winding up an attack, if the behaviour has one.
+    #[serde(default)]
+    pub telegraph: Option<(usize, usize)>,
+    pub frame_duration: f32,
+    pub collider_radius: f32,
+    /// Spawned enemies get a speed from `start` to `end`, both included.
+    pub speed: Range<f32>,
+    /// Relative chance to be picked for a spawn.
+    pub weight: f32,
+    /// Score from which this archetype starts spawning.
+    #[serde(default)]
+    pub min_score: f32,
+    /// Most of these alive at once, growing from `start` with no difficulty to `end` at full
+    /// difficulty. Unlimited when unset.
+    #[serde(default)]
+    pub budget: Option<Range<f32>>,
+    /// Harmless enemies never kill the player on contact.
+    #[serde(default = "default_dangerous")]
+    pub dangerous: bool,
+    #[serde(default)]
+    pub merges: bool,
+    pub behaviour: Behaviour,
+    /// How it moves when the behaviour does not take over.
+    #[serde(default)]
+    pub steering: Steering,
+}
+
+fn default_dangerous() -> bool {
+    true
+}
+
+impl EnemyArchetype {
+    /// How many of these may be alive at once at `difficulty`, from 0 to 1.
+    pub fn budget(&self, difficulty: f32) -> usize {
+        match &self.budget {
+            Some(budget) => (budget.start + (budget.end - budget.start) * difficulty) as usize,
+            None => usize::MAX,
+        }
+    }
+
+    /// A random speed from the `speed` range.
+    pub fn random_speed<R: Rng + ?Sized>(&self, rng: &mut R) -> f32 {
+        rng.gen_range(self.speed.start..=self.speed.end)
+    }
+
+    /// Finds the first value that would break spawning or animating these enemies.
+    fn validate(&self) -> Result<(), String> {
+        if self.frames == 0 {
+            return Err("frames must not be 0".to_owned());
+        }
+        for (name, animation) in [("stalk", Some(self.stalk)), ("telegraph", self.telegraph)] {
+            match animation {
+                Some((first, last)) if first > last || last >= self.frames => {
+                    return Err(format!(
+                        "{} frames ({}, {}) do not fit in {} frames",
+                        name, first, last, self.frames
+                    ));
+                }
+                _ => (),
+            }
+        }
+        let positive = [
+            ("frame_duration", self.frame_duration),
+            ("collider_radius", self.collider_radius),
+        ];
+        if let Some((name, value)) = positive
+            .into_iter()
+            .find(|(_, value)| value.is_nan() || *value <= 0.)
+        {
+            return Err(format!("{} must be above 0, not {}", name, value));
+        }
+        let Range { start, end } = self.speed;
+        if start.is_nan() || end.is_nan() || start < 0. || start > end {
+            return Err(format!("speed {}..{} is not a range of speeds", start, end));
+        }
+        if self.weight.is_nan() || self.weight < 0. {
+            return Err(format!("weight must not be negative, not {}", self.weight));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub enum Behaviour {
+    /// Walks straight at the player and shies away from light.
+    Stalker,
+    /// Flies to the nearest light and puts out the lantern when it reaches the player.
+    Moth {
+        sense_distance: f32,
+        extinguish_distance: f32,
+    },
+    /// Stops to wind up when close, then dashes at `dash_speed` times its speed.
+    Dasher {
+        range: f32,
+        wind_up: f32,
+        dash_speed: f32,
+        dash_time: f32,
+        recover: f32,
+    },
+    /// Divides into `pieces` smaller, faster copies after being lit for `split_after` seconds.
+    Splitter {
+        split_after: f32,
+        pieces: usize,
+        generations: u32,
+    },
+}
+
+impl Behaviour {
+    fn insert(&self, entity: &mut EntityCommands, speed: f32) {
+        match *self {
+            Behaviour::Stalker => {
+                entity.insert(Stalker);
+            }
+            Behaviour::Moth {
+                sense_distance,
+                extinguish_distance,
+            } => {
+                entity.insert(Moth {
+                    sense_distance,
+                    extinguish_distance,
+                });
+            }
+            Behaviour::Dasher {
+                range,
+                wind_up,
+                dash_speed,
+                dash_time,
+                recover,
+            } => {
+                entity.insert(Dasher {
+                    range,
+                    wind_up,
+                    dash_speed: speed * dash_speed,
+                    dash_time,
+                    recover,
+                    cruise_speed: speed,
+                    phase: DashPhase::Approach,
+                    time_left: 0.,
+                    heading: Vec2::ZERO,
+                });
+            }
+            Behaviour::Splitter {
+                split_after,
+                pieces,
+                generations,
+            } => {
+                entity.insert(Splitter {
+                    split_after,
+                    pieces,
+                    generations,
+                    lit_for: 0.,
+                });
+            }
+        }
+    }
+}
+
+/// Every enemy archetype that can spawn.
+///
+/// Starts out with the stalker alone and is replaced whenever the asset is loaded or hot-reloaded
+/// with valid archetypes.
+#[derive(Clone, Deserialize, TypeUuid)]
+#[uuid = "c2a7e915-5d3b-4f8a-a0c6-7e41b9d2f305"]
+#[serde(transparent)]
+pub struct EnemyArchetypes {
+    pub archetypes: Vec<EnemyArchetype>,
+}
+
+impl Default for EnemyArchetypes {
+    fn default() -> Self {
+        EnemyArchetypes {
+            archetypes: vec![EnemyArchetype {
+                name: "stalker".to_owned(),
+                atlas: "enemy.png".to_owned(),
+                frames: 8,
+                stalk: (0, 7),
+                telegraph: None,
+                frame_duration: 0.1,
+                collider_radius: 2.5,
+                speed: 1.0..18.0,
+                weight: 1.0,
+                min_score: 0.0,
+                budget: None,
+                dangerous: true,
+                merges: true,
+                behaviour: Behaviour::Stalker,
+                steering: Steering {
+                    seek: 1.0,
+                    flee_light: 1.5,
+                    separation: 0.4,
+                    cohesion: 0.3,
+                    alignment: 0.2,
+                    wander: 0.2,
+                    orbit: 0.6,
+                    ..default()
+                },
+            }],
+        }
+    }
+}
+
+impl EnemyArchetypes {
+    /// Finds the first archetype that would break the game, naming it in the error.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.archetypes.is_empty() {
+            return Err("there must be at least one archetype".to_owned());
+        }
+        for archetype in &self.archetypes {
+            archetype
+                .validate()
+                .map_err(|error| format!("{}: {}", archetype.name, error))?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&EnemyArchetype> {
+        self.archetypes
+            .iter()
+            .find(|archetype| archetype.name == name)
+    }
+
+    /// Picks a weighted random archetype among those unlocked at `score` and `available`.
+    pub fn choose<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        score: f32,
+        available: impl Fn(&EnemyArchetype) -> bool,
+    ) -> Option<&EnemyArchetype> {
+        let unlocked: Vec<&EnemyArchetype> = self
+            .archetypes
+            .iter()
+            .filter(|archetype| archetype.min_score <= score && available(archetype))
+            .collect();
+        unlocked
+            .choose_weighted(rng, |archetype| archetype.weight)
+            .ok()
+            .copied()
+    }
+}
+
+/// Name of the `EnemyArchetype` an enemy was spawned from.
+#[derive(Component)]
+pub struct Archetype(pub String);
+
+/// Never kills the player on contact.
+#[derive(Component)]
+pub struct Harmless;
+
+#[derive(Component)]
+pub struct Stalker;
+
+#[derive(Component)]
+pub struct Moth {
+    sense_distance: f32,
+    extinguish_distance: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DashPhase {
+    Approach,
+    WindUp,
+    Dash,
+    Recover,
+}
+
+#[derive(Component)]
+pub struct Dasher {
+    range: f32,
+    wind_up: f32,
+    dash_speed: f32,
+    dash_time: f32,
+    recover: f32,
+    cruise_speed: f32,
+    phase: DashPhase,
+    time_left: f32,
+    heading: Vec2,
+}
+
+impl Dasher {
+    pub fn phase(&self) -> DashPhase {
+        self.phase
+    }
+}
+
+#[derive(Component)]
+pub struct Splitter {
+    split_after: f32,
+    pieces: usize,
+    generations: u32,
+    lit_for: f32,
+}
+
+struct EnemyArchetypesHandle(Handle<EnemyArchetypes>);
+
+#[derive(Default)]
+struct EnemyArchetypesLoader;
+
+impl AssetLoader for EnemyArchetypesLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let archetypes: EnemyArchetypes = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(archetypes));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["archetypes.ron"]
+    }
+}
+
+pub struct ArchetypePlugin;
+
+impl Plugin for ArchetypePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<EnemyArchetypes>()
+            .init_asset_loader::<EnemyArchetypesLoader>()
+            .init_resource::<EnemyArchetypes>()
+            .add_startup_system(setup)
+            .add_system_to_stage(CoreStage::PreUpdate, apply_archetypes)
+            .add_system_set_to_stage(
+                FixedUpdateStage,
+                SystemSet::on_update(GameState::InGame)
+                    .with_system(steer_moths.before(Steer))
+                    .with_system(move_dashers.after(Steer))
+                    .with_system(split_when_lit),
+            );
+    }
+}
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(EnemyArchetypesHandle(
+        asset_server.load("enemies.archetypes.ron"),
+    ));
+}
+
+fn apply_archetypes(
+    mut events: EventReader<AssetEvent<EnemyArchetypes>>,
+    assets: Res<Assets<EnemyArchetypes>>,
+    handle: Res<EnemyArchetypesHandle>,
+    mut archetypes: ResMut<EnemyArchetypes>,
+) {
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle: changed } | AssetEvent::Modified { handle: changed }
+                if *changed == handle.0 =>
+            {
+                if let Some(loaded) = assets.get(changed) {
+                    // A broken edit while the game runs keeps the archetypes it had
+                    match loaded.validate() {
+                        Ok(()) => {
+                            *archetypes = loaded.clone();
+                            info!("Loaded {} enemy archetypes", archetypes.archetypes.len());
+                        }
+                        Err(error) => warn!("Ignoring enemy archetypes: {}", error),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+pub(super) fn insert_behaviour(
+    entity: &mut EntityCommands,
+    archetype: &EnemyArchetype,
+    speed: f32,
+) {
+    entity.insert(Archetype(archetype.name.clone()));
+    archetype.behaviour.insert(entity, speed);
+}
+
+fn steer_moths(
+    mut commands: Commands,
+    light_field: Res<LightField>,
+    mut moths: Query<(Entity, &Position, &Moth, &mut Steering)>,
+    mut player: Query<(&Position, &mut Lantern), With<PlayerPosition>>,
+    mut toggled: EventWriter<LanternToggled>,
+) {
+    let (player_position, mut lantern) = match player.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    for (entity, position, moth, mut steering) in &mut moths {
+        // Without a light to fly to, moths follow the player.
+        steering.target = light_field.nearest_source(position.current, moth.sense_distance);
+
+        if lantern.0
+            && position.current.distance(player_position.current) <= moth.extinguish_distance
+        {
+            lantern.0 = false;
+            toggled.send(LanternToggled { lit: false });
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+fn move_dashers(
+    light_field: Res<LightField>,
+    config: Res<GameConfig>,
+    mut dashers: Query<(&Position, &mut Dasher, &mut MoveDirection, &mut Speed)>,
+    player: Query<&Position, (With<PlayerPosition>, Without<Enemy>)>,
+) {
+    let player = match player.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    for (position, mut dasher, mut move_direction, mut speed) in &mut dashers {
+        if light_field.light_at(position.current) >= config.enemy_freeze_light {
+            move_direction.0 = Vec2::ZERO;
+            continue;
+        }
+
+        // Steering moves dashers while they approach, they take over from there.
+        let to_player = (player.current - position.current).normalize_or_zero();
+        dasher.time_left -= FIXED_TIMESTEP as f32;
+        match dasher.phase {
+            DashPhase::Approach => {
+                if position.current.distance(player.current) <= dasher.range {
+                    dasher.phase = DashPhase::WindUp;
+                    dasher.time_left = dasher.wind_up;
+                }
+            }
+            DashPhase::WindUp => {
+                // Keep aiming until the dash is released.
+                move_direction.0 = Vec2::ZERO;
+                dasher.heading = to_player;
+                if dasher.time_left <= 0. {
+                    dasher.phase = DashPhase::Dash;
+                    dasher.time_left = dasher.dash_time;
+                    speed.0 = dasher.dash_speed;
+                }
+            }
+            DashPhase::Dash => {
+                move_direction.0 = dasher.heading;
+                if dasher.time_left <= 0. {
+                    dasher.phase = DashPhase::Recover;
+                    dasher.time_left = dasher.recover;
+                    speed.0 = dasher.cruise_speed;
+                }
+            }
+            DashPhase::Recover => {
+                move_direction.0 = to_player * 0.3;
+                if dasher.time_left <= 0. {
+                    dasher.phase = DashPhase::Approach;
+                }
+            }
+        }
+    }
+}
+
+fn split_when_lit(
+    mut commands: Commands,
+    light_field: Res<LightField>,
+    config: Res<GameConfig>,
+    archetypes: Res<EnemyArchetypes>,
+    mut rng: ResMut<GameRng>,
+    mut splitters: Query<(
+        Entity,
+        &Position,
+        &Transform,
+        &Archetype,
+        &Speed,
+        &mut Splitter,
+    )>,
+    mut spawned: EventWriter<EnemySpawned>,
+) {
+    for (entity, position, transform, archetype, speed, mut splitter) in &mut splitters {
+        // Frozen splitters are held in place, only partial light makes them divide.
+        let light = light_field.light_at(position.current);
+        if light <= 0. || light >= config.enemy_freeze_light || splitter.generations == 0 {
+            continue;
+        }
+        splitter.lit_for += FIXED_TIMESTEP as f32;
+        if splitter.lit_for < splitter.split_after {
+            continue;
+        }
+
+        let archetype = match archetypes.get(&archetype.0) {
+            Some(archetype) => archetype,
+            None => continue,
+        };
+        let scale = transform.scale.x * 0.75;
+        let radius = archetype.collider_radius * scale;
+        let offset: f32 = rng.gen_range(0f32..std::f32::consts::TAU);
+        for piece in 0..splitter.pieces {
+            let angle = offset + piece as f32 / splitter.pieces as f32 * std::f32::consts::TAU;
+            let piece_position =
+                position.current + Vec2::new(angle.cos(), angle.sin()) * radius * 2.;
+            let enemy = spawn_enemy(
+                &mut commands,
+                archetype,
+                piece_position,
+                speed.0 * 1.25,
+                &config,
+                &mut rng,
+            );
+            commands
+                .entity(enemy)
+                .insert(Transform {
+                    translation: piece_position.extend(0.),
+                    scale: Vec3::splat(scale),
+                    ..default()
+                })
+                .insert(Collider::ball(radius))
+                .insert(Splitter {
+                    split_after: splitter.split_after,
+                    pieces: splitter.pieces,
+                    generations: splitter.generations - 1,
+                    lit_for: 0.,
+                });
+            spawned.send(EnemySpawned {
+                enemy,
+                position: piece_position,
+            });
+        }
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    fn archetypes() -> EnemyArchetypes {
+        ron::de::from_str(include_str!("../../assets/enemies.archetypes.ron")).unwrap()
+    }
+
+    fn stalker() -> EnemyArchetype {
+        EnemyArchetypes::default().archetypes.remove(0)
+    }
+
+    #[test]
+    fn shipped_and_default_archetypes_are_valid() {
+        assert_eq!(archetypes().validate(), Ok(()));
+        assert_eq!(EnemyArchetypes::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_archetypes_that_break_the_game() {
+        let broken = [
+            EnemyArchetype {
+                frames: 0,
+                ..stalker()
+            },
+            EnemyArchetype {
+                stalk: (0, 8),
+                ..stalker()
+            },
+            EnemyArchetype {
+                stalk: (5, 2),
+                ..stalker()
+            },
+            EnemyArchetype {
+                telegraph: Some((4, 9)),
+                ..stalker()
+            },
+            EnemyArchetype {
+                collider_radius: 0.,
+                ..stalker()
+            },
+            EnemyArchetype {
+                frame_duration: -0.1,
+                ..stalker()
+            },
+            EnemyArchetype {
+                speed: 18.0..1.0,
+                ..stalker()
+            },
+            EnemyArchetype {
+                speed: -1.0..1.0,
+                ..stalker()
+            },
+        ];
+
+        for archetype in broken {
+            let archetypes = EnemyArchetypes {
+                archetypes: vec![archetype],
+            };
+            assert!(archetypes.validate().is_err());
+        }
+        let empty = EnemyArchetypes {
+            archetypes: Vec::new(),
+        };
+        assert!(empty.validate().is_err());
+    }
+
+    #[test]
+    fn choose_only_picks_unlocked_and_available_archetypes() {
+        let archetypes = archetypes();
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let chosen = |score, available: &dyn Fn(&EnemyArchetype) -> bool, rng: &mut ChaCha8Rng| {
+            (0..100)
+                .filter_map(|_| archetypes.choose(rng, score, available))
+                .map(|archetype| archetype.name.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let early = chosen(0., &|_| true, &mut rng);
+        assert!(early.iter().all(|name| name == "stalker"));
+
+        let late = chosen(100., &|archetype| archetype.name != "stalker", &mut rng);
+        assert!(!late.is_empty());
+        assert!(late.iter().all(|name| name != "stalker"));
+        assert!(late.iter().any(|name| name == "dasher"));
+
+        assert!(chosen(100., &|_| false, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn choose_never_picks_an_archetype_without_weight() {
+        let mut archetypes = archetypes();
+        for archetype in &mut archetypes.archetypes {
+            if archetype.name != "moth" {
+                archetype.weight = 0.;
+            }
+        }
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+
+        for _ in 0..100 {
+            let archetype = archetypes.choose(&mut rng, 100., |_| true);
+            assert_eq!(
+                archetype.map(|archetype| archetype.name.as_str()),
+                Some("moth")
+            );
+        }
+    }
+
+    #[test]
+    fn behaviours_are_read_with_their_settings() {
+        let archetypes = archetypes();
+
+        assert!(matches!(
+            archetypes
+                .get("stalker")
+                .map(|archetype| &archetype.behaviour),
+            Some(Behaviour::Stalker)
+        ));
+        assert!(matches!(
+            archetypes.get("dasher").map(|archetype| &archetype.behaviour),
+            Some(Behaviour::Dasher { range, .. }) if *range == 30.
+        ));
+        assert!(matches!(
+            archetypes
+                .get("splitter")
+                .map(|archetype| &archetype.behaviour),
+            Some(Behaviour::Splitter {
+                pieces: 2,
+                generations: 2,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,363 @@
+//! Decides when, where and which enemies spawn.
+
+use std::{collections::HashMap, time::Duration};
+
+use bevy::prelude::*;
+use rand::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    arena::Arena,
+    config::GameConfig,
+    events::EnemySpawned,
+    game::{fixed_timestep, FixedUpdateStage, GameState, RestartGame},
+    lighting::{GlobalLight, LightField},
+    rng::GameRng,
+    text::Score,
+};
+
+use super::{
+    archetype::{Archetype, EnemyArchetypes},
+    enemy::{spawn_enemy, Enemy},
+    player::PlayerPosition,
+    Position,
+};
+
+/// Spawn intervals never get shorter than this, whatever the config says.
+const MIN_SPAWN_INTERVAL: f32 = 0.05;
+
+/// Spawn points tried before a spawn is skipped because they were all visible, lit or inside walls.
+const SPAWN_ATTEMPTS: usize = 16;
+
+/// Tuning of the spawn director, part of `GameConfig`.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct DirectorConfig {
+    /// Seconds between spawns at no difficulty.
+    pub start_interval: f32,
+    /// Seconds between spawns at full difficulty.
+    pub min_interval: f32,
+    /// Score at which the difficulty reaches 1 and stops growing.
+    pub full_difficulty_score: f32,
+    /// Shape of the difficulty curve. 1 is linear, higher values ramp up later.
+    pub difficulty_exponent: f32,
+    /// Phases the director cycles through during a round.
+    pub waves: Vec<Wave>,
+    /// Width and height of the view in world units when there is no camera, e.g. when headless.
+    /// With a camera, enemies appear just outside of what it shows.
+    pub view_size: (f32, f32),
+    /// How far outside the view enemies appear.
+    pub offscreen_margin: f32,
+}
+
+impl Default for DirectorConfig {
+    fn default() -> Self {
+        DirectorConfig {
+            start_interval: 1.0,
+            min_interval: 0.3,
+            full_difficulty_score: 100.0,
+            difficulty_exponent: 1.0,
+            waves: vec![
+                Wave {
+                    name: "lull".to_owned(),
+                    duration: 6.0,
+                    interval_multiplier: 2.0,
+                },
+                Wave {
+                    name: "build".to_owned(),
+                    duration: 20.0,
+                    interval_multiplier: 1.0,
+                },
+                Wave {
+                    name: "surge".to_owned(),
+                    duration: 8.0,
+                    interval_multiplier: 0.4,
+                },
+            ],
+            view_size: (192.0, 108.0),
+            offscreen_margin: 4.0,
+        }
+    }
+}
+
+impl DirectorConfig {
+    /// How far along the difficulty curve a round is at `score`, from 0 to 1.
+    pub fn difficulty(&self, score: f32) -> f32 {
+        if self.full_difficulty_score <= 0. {
+            return 1.;
+        }
+        (score / self.full_difficulty_score)
+            .clamp(0., 1.)
+            .powf(self.difficulty_exponent.max(0.))
+    }
+
+    /// Half extents of the view grown by the margin, spawns happen just outside of it.
+    ///
+    /// The view is what `camera` shows, or `view_size` without one.
+    pub fn spawn_half_extents(&self, camera: Option<&OrthographicProjection>) -> Vec2 {
+        let view = match camera {
+            Some(projection) => {
+                Vec2::new(
+                    projection.right - projection.left,
+                    projection.top - projection.bottom,
+                ) * projection.scale
+            }
+            None => Vec2::new(self.view_size.0, self.view_size.1),
+        };
+        view / 2. + Vec2::splat(self.offscreen_margin)
+    }
+
+    /// Time until the next spawn at `score`, during wave number `wave`.
+    pub fn spawn_interval(&self, score: f32, wave: usize) -> Duration {
+        let difficulty = self.difficulty(score);
+        let interval = self.start_interval + (self.min_interval - self.start_interval) * difficulty;
+        let multiplier = self
+            .waves
+            .get(wave)
+            .map_or(1., |wave| wave.interval_multiplier);
+        Duration::from_secs_f32((interval * multiplier).max(MIN_SPAWN_INTERVAL))
+    }
+}
+
+/// One phase of the spawn rhythm.
+#[derive(Clone, Deserialize)]
+pub struct Wave {
+    pub name: String,
+    /// Seconds the phase lasts.
+    pub duration: f32,
+    /// Scales the spawn interval: above 1 is a lull, below 1 a surge.
+    pub interval_multiplier: f32,
+}
+
+/// State of the spawn director, on the "Enemy Spawner" entity.
+#[derive(Component)]
+pub struct Director {
+    spawn_timer: Timer,
+    wave: usize,
+    wave_timer: Timer,
+}
+
+impl Director {
+    pub fn new(config: &DirectorConfig) -> Self {
+        Director {
+            spawn_timer: Timer::new(config.spawn_interval(0., 0), false),
+            wave: 0,
+            wave_timer: wave_timer(config, 0),
+        }
+    }
+
+    /// Index of the current wave in `DirectorConfig::waves`.
+    pub fn wave(&self) -> usize {
+        self.wave
+    }
+}
+
+fn wave_timer(config: &DirectorConfig, wave: usize) -> Timer {
+    let duration = config.waves.get(wave).map_or(0., |wave| wave.duration);
+    Timer::from_seconds(duration.max(MIN_SPAWN_INTERVAL), false)
+}
+
+pub struct DirectorPlugin;
+
+impl Plugin for DirectorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup)
+            .add_system_set_to_stage(
+                FixedUpdateStage,
+                SystemSet::on_update(GameState::InGame).with_system(direct_spawns),
+            )
+            .add_system(reset_director);
+    }
+}
+
+fn setup(mut commands: Commands, config: Res<GameConfig>) {
+    commands
+        .spawn()
+        .insert(Name::new("Enemy Spawner".to_string()))
+        .insert(Director::new(&config.director));
+}
+
+/// A point just outside the rectangle of `half_extents` around `center`, in a random direction.
+fn offscreen_point<R: Rng + ?Sized>(rng: &mut R, center: Vec2, half_extents: Vec2) -> Vec2 {
+    let angle: f32 = rng.gen_range(0f32..std::f32::consts::TAU);
+    let direction = Vec2::new(angle.cos(), angle.sin());
+    let distance = (half_extents.x / direction.x.abs()).min(half_extents.y / direction.y.abs());
+    center + direction * distance
+}
+
+fn direct_spawns(
+    mut commands: Commands,
+    global_light: Query<&GlobalLight>,
+    player: Query<&Position, With<PlayerPosition>>,
+    camera: Query<&OrthographicProjection, With<Camera2d>>,
+    score: Query<&Score>,
+    enemies: Query<&Archetype, With<Enemy>>,
+    mut director: Query<&mut Director>,
+    mut rng: ResMut<GameRng>,
+    light_field: Res<LightField>,
+    arena: Res<Arena>,
+    config: Res<GameConfig>,
+    archetypes: Res<EnemyArchetypes>,
+    mut spawned: EventWriter<EnemySpawned>,
+) {
+    if global_light.iter().any(|light| light.0) {
+        return;
+    }
+    let player = match player.get_single() {
+        Ok(position) => position.current,
+        Err(_) => return,
+    };
+    let config = &*config;
+    let director_config = &config.director;
+    let mut director = director.single_mut();
+
+    director.wave_timer.tick(fixed_timestep());
+    if director.wave_timer.finished() && !director_config.waves.is_empty() {
+        director.wave = (director.wave + 1) % director_config.waves.len();
+        director.wave_timer = wave_timer(director_config, director.wave);
+    }
+
+    director.spawn_timer.tick(fixed_timestep());
+    if !director.spawn_timer.finished() {
+        return;
+    }
+    let score = score.single().0;
+    director.spawn_timer = Timer::new(director_config.spawn_interval(score, director.wave), false);
+
+    let difficulty = director_config.difficulty(score);
+    let mut alive: HashMap<&str, usize> = HashMap::new();
+    for archetype in &enemies {
+        *alive.entry(archetype.0.as_str()).or_default() += 1;
+    }
+    let archetype = match archetypes.choose(&mut *rng, score, |archetype| {
+        archetype.budget(difficulty) > alive.get(archetype.name.as_str()).copied().unwrap_or(0)
+    }) {
+        Some(archetype) => archetype,
+        None => return,
+    };
+
+    // Around the gameplay position, as the camera follows the player
+    let half_extents = director_config.spawn_half_extents(camera.get_single().ok());
+    let position = (0..SPAWN_ATTEMPTS)
+        .map(|_| match arena.spawn_zones.choose(&mut *rng) {
+            Some(zone) => zone.random_point(&mut *rng),
+            None => offscreen_point(&mut *rng, player, half_extents),
+        })
+        .find(|position| {
+            let in_view = (*position - player).abs().cmplt(half_extents).all();
+            !in_view && !light_field.is_lit(*position) && arena.is_open(*position)
+        });
+    let position = match position {
+        Some(position) => position,
+        None => return,
+    };
+
+    let speed = archetype.random_speed(&mut *rng);
+    let enemy = spawn_enemy(&mut commands, archetype, position, speed, config, &mut rng);
+    spawned.send(EnemySpawned { enemy, position });
+}
+
+fn reset_director(
+    mut events: EventReader<RestartGame>,
+    mut director: Query<&mut Director>,
+    config: Res<GameConfig>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    for mut director in &mut director {
+        *director = Director::new(&config.director);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    #[test]
+    fn difficulty_follows_the_curve_and_is_clamped() {
+        let linear = DirectorConfig::default();
+        assert_eq!(linear.difficulty(0.), 0.);
+        assert_eq!(linear.difficulty(50.), 0.5);
+        assert_eq!(linear.difficulty(250.), 1.);
+        assert_eq!(linear.difficulty(-10.), 0.);
+
+        let late = DirectorConfig {
+            difficulty_exponent: 2.,
+            ..default()
+        };
+        assert_eq!(late.difficulty(50.), 0.25);
+
+        let instant = DirectorConfig {
+            full_difficulty_score: 0.,
+            ..default()
+        };
+        assert_eq!(instant.difficulty(0.), 1.);
+    }
+
+    #[test]
+    fn spawn_interval_ramps_down_with_the_difficulty() {
+        let config = DirectorConfig {
+            waves: Vec::new(),
+            ..default()
+        };
+        let seconds = |score| config.spawn_interval(score, 0).as_secs_f32();
+
+        assert!((seconds(0.) - 1.0).abs() < 1e-4);
+        assert!((seconds(50.) - 0.65).abs() < 1e-4);
+        assert!((seconds(100.) - 0.3).abs() < 1e-4);
+        assert!((seconds(1000.) - 0.3).abs() < 1e-4);
+    }
+
+    #[test]
+    fn spawn_interval_is_scaled_by_the_wave_and_floored() {
+        let config = DirectorConfig::default();
+        let seconds = |wave| config.spawn_interval(0., wave).as_secs_f32();
+
+        // lull, build and surge
+        assert!((seconds(0) - 2.0).abs() < 1e-4);
+        assert!((seconds(1) - 1.0).abs() < 1e-4);
+        assert!((seconds(2) - 0.4).abs() < 1e-4);
+
+        let frantic = DirectorConfig {
+            start_interval: 0.01,
+            ..default()
+        };
+        assert!(frantic.spawn_interval(0., 1).as_secs_f32() >= MIN_SPAWN_INTERVAL);
+    }
+
+    #[test]
+    fn offscreen_points_lie_just_outside_the_view() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let center = Vec2::new(-500., 1200.);
+        let half_extents = Vec2::new(100., 58.);
+
+        for _ in 0..200 {
+            let offset = offscreen_point(&mut rng, center, half_extents) - center;
+            let edge = (offset / half_extents).abs().max_element();
+            assert!((edge - 1.).abs() < 1e-3, "{} is not on the edge", offset);
+        }
+    }
+
+    #[test]
+    fn spawn_half_extents_grow_the_view_by_the_margin() {
+        let config = DirectorConfig::default();
+        assert_eq!(config.spawn_half_extents(None), Vec2::new(100., 58.));
+
+        let projection = OrthographicProjection {
+            left: -640.,
+            right: 640.,
+            bottom: -360.,
+            top: 360.,
+            scale: 0.25,
+            ..default()
+        };
+        assert_eq!(
+            config.spawn_half_extents(Some(&projection)),
+            Vec2::new(164., 94.)
+        );
+    }
+}
@@ -1,190 +1,182 @@
-use std::time::Duration;
-
 use crate::{
     animation::Anim,
-    character::player::LanternTimer,
-    game::GameState,
-    lighting::{GlobalLight, Lighting},
+    config::GameConfig,
+    events::{EnemyBecameDangerous, PlayerDied},
+    game::{fixed_timestep, FixedUpdateStage, GameState, RestartGame},
+    lighting::LightField,
+    physics::{Knockback, PhysicsSchedule, PhysicsStage, ENEMY_GROUP, WORLD_GROUP},
     rendering,
-    text::{MainText, Score, SubText},
+    rng::GameRng,
+    text::Score,
+};
+use bevy::{prelude::*, sprite::Anchor, utils::HashMap};
+use bevy_rapier2d::prelude::{
+    Collider, GravityScale, LockedAxes, RapierContext, RigidBody, SolverGroups, Velocity,
 };
-use bevy::{prelude::*, sprite::Anchor};
-use bevy_rapier2d::prelude::{Collider, RapierContext, Sensor};
-use rand::prelude::*;
 
-use super::{player::PlayerPosition, AnimationTimer, GameOver, MoveDirection, Speed};
+use super::{
+    archetype::{
+        insert_behaviour, Archetype, DashPhase, Dasher, EnemyArchetype, EnemyArchetypes, Harmless,
+    },
+    merge::Merge,
+    AnimationTimer, GameOver, MoveDirection, Position, Speed,
+};
 
 #[derive(Component)]
 pub struct Enemy;
 
-#[derive(Component)]
-struct EnemySpawnTimer(Timer);
-
-struct EnemyAtlas(Handle<TextureAtlas>);
+/// Sprite sheet per archetype name.
+#[derive(Default)]
+struct EnemyAtlases(HashMap<String, Handle<TextureAtlas>>);
 
 #[derive(Component)]
 struct EnemyAnimations {
     stalk: Anim,
+    telegraph: Anim,
 }
 
 #[derive(Component)]
 struct DangerousTimer(Timer);
 
+/// Can hurt the player and merge. Dangerous enemies pass through the player and each other,
+/// but are still blocked by level geometry.
 #[derive(Component)]
-struct Merge(usize);
+pub struct Dangerous;
 
 pub struct EnemyPlugin;
 impl Plugin for EnemyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(setup)
-            .add_system_set(
-                SystemSet::on_update(GameState::InGame)
-                    .with_system(spawn_enemies)
-                    .with_system(enemy_intersecting_player)
-                    .with_system(hide_in_light)
-                    .with_system(make_dangerous),
-            )
+        app.world
+            .resource_mut::<PhysicsSchedule>()
+            .0
+            .add_system_to_stage(PhysicsStage::Resolve, enemy_intersecting_player);
+        app.add_system_set(SystemSet::on_update(GameState::InGame).with_system(hide_in_light))
             .add_system_set_to_stage(
-                CoreStage::PostUpdate,
-                SystemSet::on_update(GameState::InGame).with_system(merge),
+                FixedUpdateStage,
+                SystemSet::on_update(GameState::InGame).with_system(make_dangerous),
             )
-            .add_system(set_move_to_player)
+            .add_system(reset_enemies);
+    }
+}
+
+pub struct EnemySpritePlugin;
+impl Plugin for EnemySpritePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EnemyAtlases>()
+            .add_system(load_atlases)
+            .add_system_to_stage(CoreStage::PostUpdate, insert_sprite)
             .add_system(sprite_animation);
     }
 }
 
-fn setup(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-) {
-    let texture_handle = asset_server.load("enemy.png");
-    let texture_atlas = TextureAtlas::from_grid(texture_handle, Vec2::new(16f32, 16f32), 8, 1);
-    let texture_atlas_handle = texture_atlases.add(texture_atlas);
+/// Spawns an enemy of `archetype`, which needs `DangerousTimer` to run out before it can hurt the player.
+pub(super) fn spawn_enemy(
+    commands: &mut Commands,
+    archetype: &EnemyArchetype,
+    position: Vec2,
+    speed: f32,
+    config: &GameConfig,
+    rng: &mut GameRng,
+) -> Entity {
+    let (stalk_start, stalk_end) = archetype.stalk;
+    let (telegraph_start, telegraph_end) = archetype.telegraph.unwrap_or(archetype.stalk);
 
-    commands.insert_resource(EnemyAtlas(texture_atlas_handle));
-    commands
-        .spawn()
-        .insert(Name::new("Enemy Spawner".to_string()))
-        .insert(EnemySpawnTimer(Timer::new(Duration::from_secs(1), true)));
+    let mut enemy = commands.spawn_bundle(SpatialBundle {
+        transform: Transform::from_translation(position.extend(0.)),
+        ..default()
+    });
+    enemy
+        .insert(Enemy)
+        .insert(Position::new(position))
+        .insert(RigidBody::Dynamic)
+        .insert(Collider::ball(archetype.collider_radius))
+        .insert(SolverGroups::new(ENEMY_GROUP, u32::MAX))
+        .insert(LockedAxes::ROTATION_LOCKED)
+        .insert(GravityScale(0.))
+        .insert(Velocity::zero())
+        .insert(Knockback::default())
+        .insert(MoveDirection(Vec2::default()))
+        .insert(Speed(speed))
+        .insert(EnemyAnimations {
+            stalk: Anim::new(stalk_start, stalk_end),
+            telegraph: Anim::new(telegraph_start, telegraph_end),
+        })
+        .insert(AnimationTimer(Timer::from_seconds(
+            archetype.frame_duration,
+            true,
+        )))
+        .insert(rendering::OrderedZ)
+        .insert(DangerousTimer(Timer::from_seconds(
+            config.dangerous_after,
+            false,
+        )));
+    if !archetype.dangerous {
+        enemy.insert(Harmless);
+    }
+    if archetype.merges {
+        enemy.insert(Merge::new(speed));
+    }
+    enemy.insert(archetype.steering.randomized(rng));
+    insert_behaviour(&mut enemy, archetype, speed);
+    enemy.id()
 }
 
-fn spawn_enemies(
-    mut commands: Commands,
-    query: Query<&GlobalLight>,
-    player_query: Query<&Transform, With<PlayerPosition>>,
-    score_query: Query<&Score>,
-    mut timer_query: Query<&mut EnemySpawnTimer>,
-    time: Res<Time>,
-    texture_atlas: Res<EnemyAtlas>,
+fn load_atlases(
+    archetypes: Res<EnemyArchetypes>,
+    asset_server: Res<AssetServer>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut atlases: ResMut<EnemyAtlases>,
 ) {
-    for global_light in &query {
-        for player in &player_query {
-            if !global_light.0 {
-                let mut timer = timer_query.single_mut();
+    if !archetypes.is_changed() {
+        return;
+    }
 
-                if timer.0.just_finished() {
-                    timer.0.set_duration(Duration::from_secs_f32(
-                        1.0 - (score_query.single().0) / 100.0,
-                    ));
-                    let angle: f32 = rand::thread_rng().gen_range(0f32..std::f32::consts::TAU);
-                    let distance = 60f32;
-                    let x = player.translation.x + (angle.cos() * distance);
-                    let y = player.translation.y + (angle.sin() * distance);
-                    commands
-                        .spawn_bundle(SpriteSheetBundle {
-                            sprite: TextureAtlasSprite {
-                                anchor: Anchor::BottomCenter,
-                                ..default()
-                            },
-                            texture_atlas: texture_atlas.0.clone(),
-                            transform: Transform::from_xyz(x, y, 0.),
-                            ..default()
-                        })
-                        .insert(Enemy)
-                        .insert(Collider::ball(2.5))
-                        .insert(MoveDirection(Vec2::default()))
-                        .insert(Speed(rand::thread_rng().gen_range(1f32..18f32)))
-                        .insert(EnemyAnimations {
-                            stalk: Anim::new(0, 7),
-                        })
-                        .insert(AnimationTimer(Timer::from_seconds(0.1, true)))
-                        .insert(rendering::OrderedZ)
-                        .insert(DangerousTimer(Timer::from_seconds(0.5, false)))
-                        .insert(Merge(0));
-                    timer.0.reset();
-                } else {
-                    timer.0.tick(time.delta());
-                }
-            }
-        }
+    for archetype in &archetypes.archetypes {
+        let texture_handle = asset_server.load(archetype.atlas.as_str());
+        let texture_atlas =
+            TextureAtlas::from_grid(texture_handle, Vec2::new(16f32, 16f32), archetype.frames, 1);
+        atlases
+            .0
+            .insert(archetype.name.clone(), texture_atlases.add(texture_atlas));
     }
 }
 
-fn merge(
+fn insert_sprite(
     mut commands: Commands,
-    rapier: Res<RapierContext>,
-    mut query: Query<
-        (
-            Entity,
-            &mut Transform,
-            &mut Collider,
-            &mut Speed,
-            &mut Merge,
-        ),
-        (With<Enemy>, With<Sensor>),
-    >,
+    query: Query<(Entity, &Archetype), Added<Enemy>>,
+    atlases: Res<EnemyAtlases>,
 ) {
-    rapier.intersection_pairs().for_each(|(e1, e2, _)| {
-        let entities_result = query.get_many_mut([e1, e2]);
-        if let Ok(mut entities) = entities_result {
-            let speed1 = entities[0].3 .0;
-            let speed2 = entities[1].3 .0;
-            let merge1 = entities[0].4 .0;
-            let merge2 = entities[1].4 .0;
-            if merge1 >= merge2 && merge1 < 5 {
-                let most_merged_entity = entities.get_mut(0).unwrap();
-                bevy::log::info!("merge {} into {}", merge1, merge2);
-
-                most_merged_entity.1.scale *= 1.1;
-                most_merged_entity.3 .0 = speed1.max(speed2);
-
-                let mut ball = most_merged_entity.2.as_ball_mut().unwrap();
-                ball.set_radius(ball.radius() * 1.1);
-                most_merged_entity.4 .0 += 1;
-
-                commands
-                    .entity(entities.get_mut(1).unwrap().0)
-                    .despawn_recursive();
-            } else if merge2 >= merge1 && merge2 < 5 {
-                let most_merged_entity = entities.get_mut(1).unwrap();
-                bevy::log::info!("merge {} into {}", merge2, merge1);
-                most_merged_entity.1.scale *= 1.1;
-                most_merged_entity.3 .0 = speed1.max(speed2);
-
-                let mut ball = most_merged_entity.2.as_ball_mut().unwrap();
-                ball.set_radius(ball.radius() * 1.1);
-                most_merged_entity.4 .0 += 1;
-                bevy::log::info!("{}", most_merged_entity.4 .0);
-                commands
-                    .entity(entities.get_mut(0).unwrap().0)
-                    .despawn_recursive();
-            };
+    for (entity, archetype) in &query {
+        if let Some(texture_atlas) = atlases.0.get(&archetype.0) {
+            commands
+                .entity(entity)
+                .insert(TextureAtlasSprite {
+                    anchor: Anchor::BottomCenter,
+                    ..default()
+                })
+                .insert(texture_atlas.clone());
         }
-    });
+    }
 }
 
 fn make_dangerous(
     mut commands: Commands,
-    time: Res<Time>,
-    mut query: Query<(Entity, &mut DangerousTimer)>,
+    mut query: Query<(Entity, &Position, &mut DangerousTimer), Without<Dangerous>>,
+    mut became_dangerous: EventWriter<EnemyBecameDangerous>,
 ) {
-    for (entity, mut dangerous) in &mut query {
+    for (entity, position, mut dangerous) in &mut query {
         if dangerous.0.just_finished() {
-            commands.entity(entity).insert(Sensor);
+            commands
+                .entity(entity)
+                .insert(Dangerous)
+                .insert(SolverGroups::new(ENEMY_GROUP, WORLD_GROUP));
             dangerous.0.reset();
+            became_dangerous.send(EnemyBecameDangerous {
+                enemy: entity,
+                position: position.current,
+            });
         } else {
-            dangerous.0.tick(time.delta());
+            dangerous.0.tick(fixed_timestep());
         }
     }
 }
@@ -195,85 +187,89 @@ fn sprite_animation(
         &mut EnemyAnimations,
         &mut AnimationTimer,
         &mut TextureAtlasSprite,
+        &MoveDirection,
+        Option<&Dasher>,
     )>,
-    lighting_query: Query<&GlobalLight>,
 ) {
-    for (mut anims, mut timer, mut sprite) in &mut query {
+    for (mut anims, mut timer, mut sprite, direction, dasher) in &mut query {
         timer.tick(time.delta());
-        if let Ok(global_light) = lighting_query.get_single() {
-            if !global_light.0 && timer.just_finished() {
-                sprite.index = anims.stalk.step()
-            }
+        let telegraphing = dasher.map_or(false, |dasher| dasher.phase() == DashPhase::WindUp);
+        if let Some(dasher) = dasher {
+            sprite.color = match dasher.phase() {
+                DashPhase::WindUp => Color::rgb(0.8, 0.1, 0.1),
+                _ => Color::WHITE,
+            };
         }
-    }
-}
-
-fn set_move_to_player(
-    mut enemy_query: Query<(&Transform, &mut MoveDirection), With<Enemy>>,
-    player_query: Query<&Transform, With<PlayerPosition>>,
-    lighting_query: Query<&Lighting>,
-) {
-    let player = player_query.single();
-    if let Ok(lighting) = lighting_query.get_single() {
-        for (enemy_transform, mut move_direction) in &mut enemy_query {
-            let delta_x = player.translation.x - enemy_transform.translation.x;
-            let delta_y = player.translation.y - enemy_transform.translation.y;
-
-            let mut direction = Vec2::new(delta_x, delta_y).normalize_or_zero();
-
-            match lighting.0 {
-                crate::lighting::LightingMode::Lantern => {
-                    direction.x /= 2.0;
-                    direction.y /= 2.0;
-                }
-                crate::lighting::LightingMode::Light => {
-                    direction.x = 0.0;
-                    direction.y = 0.0;
-                }
-                _ => (),
-            }
-            move_direction.0.x = direction.x;
-            move_direction.0.y = direction.y;
+        if !timer.just_finished() {
+            continue;
+        }
+        if telegraphing {
+            sprite.index = anims.telegraph.step();
+        } else if direction.0 != Vec2::ZERO {
+            // Frozen enemies hold still until the light leaves them.
+            sprite.index = anims.stalk.step();
         }
     }
 }
 
+/// Checked after every physics step, so no contact is missed when several steps run in a frame.
 fn enemy_intersecting_player(
-    mut commands: Commands,
-    mut state: ResMut<State<GameState>>,
+    state: Res<State<GameState>>,
     rapier: Res<RapierContext>,
-    mut player: Query<(&mut MainText, &mut SubText, Entity, &mut GameOver)>,
-    mut camera: Query<&mut OrthographicProjection>,
+    mut player: Query<(Entity, &mut GameOver)>,
+    dangerous: Query<(), (With<Dangerous>, Without<Harmless>)>,
     score: Query<&Score>,
+    mut died: EventWriter<PlayerDied>,
 ) {
-    for (mut text, mut sub, entity, mut game_over) in &mut player {
-        let intersections: Vec<(Entity, Entity, bool)> =
-            rapier.intersections_with(entity).collect();
+    if state.current() != &GameState::InGame {
+        return;
+    }
+
+    for (entity, mut game_over) in &mut player {
+        if game_over.0 {
+            continue;
+        }
 
-        if !intersections.is_empty() {
-            bevy::log::info!("{}", intersections.len());
+        if rapier.contacts_with(entity).any(|contact| {
+            let other = if contact.collider1() == entity {
+                contact.collider2()
+            } else {
+                contact.collider1()
+            };
+            contact.has_any_active_contacts() && dangerous.contains(other)
+        }) {
             game_over.0 = true;
-            for mut projection in &mut camera {
-                projection.scale = 0.08;
-                text.0 = "GAME OVER".to_owned();
-                sub.0 = format!("Score: {}", score.single().0.round().to_owned());
-            }
-            state.set(GameState::GameOver).unwrap();
-            commands
-                .entity(entity)
-                .insert(Sensor)
-                .insert(LanternTimer(Timer::from_seconds(5.0, false)));
+            died.send(PlayerDied {
+                player: entity,
+                score: score.single().0,
+            });
         }
     }
 }
 
 fn hide_in_light(
-    mut query: Query<&mut Visibility, With<Enemy>>,
-    global_light: Query<&GlobalLight, Changed<GlobalLight>>,
+    mut query: Query<(&Position, &mut Visibility), With<Enemy>>,
+    light_field: Res<LightField>,
+    config: Res<GameConfig>,
 ) {
-    for light in &global_light {
-        for mut visibility in &mut query {
-            visibility.is_visible = !light.0;
+    for (position, mut visibility) in &mut query {
+        let is_visible = light_field.light_at(position.current) < config.enemy_freeze_light;
+        if visibility.is_visible != is_visible {
+            visibility.is_visible = is_visible;
         }
     }
 }
+
+fn reset_enemies(
+    mut commands: Commands,
+    mut events: EventReader<RestartGame>,
+    enemies: Query<Entity, With<Enemy>>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    for entity in &enemies {
+        commands.entity(entity).despawn_recursive();
+    }
+}
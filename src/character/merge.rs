@@ -0,0 +1,328 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::{Collider, RapierContext};
+use serde::Deserialize;
+
+use crate::{
+    config::GameConfig,
+    game::GameState,
+    physics::{Knockback, PhysicsSchedule, PhysicsStage},
+};
+
+use super::{
+    enemy::{Dangerous, Enemy},
+    AnimationTimer, Position, Speed,
+};
+
+const MERGE_FLASH_SECONDS: f32 = 0.15;
+
+/// How many times an enemy has absorbed another, and the speed it was spawned with.
+#[derive(Component)]
+pub struct Merge {
+    pub level: usize,
+    base_speed: f32,
+}
+
+impl Merge {
+    pub fn new(base_speed: f32) -> Self {
+        Merge {
+            level: 0,
+            base_speed,
+        }
+    }
+}
+
+/// Sent when `absorbed` has been merged into `survivor`, which is now at `level`.
+pub struct EnemyMerged {
+    pub survivor: Entity,
+    pub absorbed: Entity,
+    pub level: usize,
+}
+
+/// Stats of an enemy at one merge level.
+#[derive(Clone, Deserialize)]
+pub struct MergeLevel {
+    /// Applied to the faster of the two merged enemies' spawn speeds.
+    pub speed_multiplier: f32,
+    pub radius: f32,
+    pub scale: f32,
+    pub tint: [f32; 4],
+    /// Seconds per animation frame.
+    pub frame_duration: f32,
+}
+
+/// How the level of the survivor is derived from the two merging enemies.
+#[derive(Clone, Copy, Deserialize)]
+pub enum MergeRule {
+    /// The higher level survives and gains one level.
+    Increment,
+    /// The higher level survives and gains the absorbed enemy's levels plus one.
+    Sum,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct MergeOutcome {
+    /// Whether the first of the two enemies survives.
+    pub first_survives: bool,
+    pub level: usize,
+}
+
+impl MergeRule {
+    /// Decides whether two enemies at `first` and `second` merge, given the highest level.
+    ///
+    /// Enemies already at `max_level` absorb nothing, and nothing can absorb them.
+    pub fn resolve(self, first: usize, second: usize, max_level: usize) -> Option<MergeOutcome> {
+        let first_survives = first >= second;
+        let (survivor, absorbed) = if first_survives {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        if survivor >= max_level {
+            return None;
+        }
+
+        let level = match self {
+            MergeRule::Increment => survivor + 1,
+            MergeRule::Sum => survivor + absorbed + 1,
+        };
+        Some(MergeOutcome {
+            first_survives,
+            level: level.min(max_level),
+        })
+    }
+}
+
+#[derive(Component)]
+struct MergeFlash(Timer);
+
+pub struct MergePlugin;
+
+impl Plugin for MergePlugin {
+    fn build(&self, app: &mut App) {
+        app.world
+            .resource_mut::<PhysicsSchedule>()
+            .0
+            .add_system_to_stage(PhysicsStage::Resolve, merge)
+            .add_system_to_stage(PhysicsStage::Resolve, knock_back_on_merge.after(merge));
+        app.add_event::<EnemyMerged>();
+    }
+}
+
+pub struct MergeSpritePlugin;
+
+impl Plugin for MergeSpritePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_to_stage(CoreStage::PostUpdate, insert_merge_flash)
+            .add_system(apply_merge_visuals)
+            .add_system(flash_on_merge);
+    }
+}
+
+/// Merges the enemies touching after every physics step.
+fn merge(
+    mut commands: Commands,
+    state: Res<State<GameState>>,
+    rapier: Res<RapierContext>,
+    config: Res<GameConfig>,
+    mut merged: EventWriter<EnemyMerged>,
+    mut query: Query<
+        (
+            Entity,
+            &mut Transform,
+            &mut Collider,
+            &mut Speed,
+            &mut Merge,
+        ),
+        (With<Enemy>, With<Dangerous>),
+    >,
+) {
+    if state.current() != &GameState::InGame {
+        return;
+    }
+
+    let max_level = config.merge_levels.len().saturating_sub(1);
+    let mut absorbed = HashSet::new();
+
+    for contact in rapier.contact_pairs() {
+        let (e1, e2) = (contact.collider1(), contact.collider2());
+        if !contact.has_any_active_contacts() || absorbed.contains(&e1) || absorbed.contains(&e2) {
+            continue;
+        }
+        if let Ok(mut entities) = query.get_many_mut([e1, e2]) {
+            let outcome =
+                match config
+                    .merge_rule
+                    .resolve(entities[0].4.level, entities[1].4.level, max_level)
+                {
+                    Some(outcome) => outcome,
+                    None => continue,
+                };
+            let base_speed = entities[0].4.base_speed.max(entities[1].4.base_speed);
+            let (survivor, other) = if outcome.first_survives {
+                (0, 1)
+            } else {
+                (1, 0)
+            };
+            let survivor_entity = entities[survivor].0;
+            let absorbed_entity = entities[other].0;
+            let stats = &config.merge_levels[outcome.level];
+
+            let (_, transform, collider, speed, merge) = &mut entities[survivor];
+            merge.level = outcome.level;
+            merge.base_speed = base_speed;
+            speed.0 = base_speed * stats.speed_multiplier;
+            transform.scale = Vec3::splat(stats.scale);
+            if let Some(mut ball) = collider.as_ball_mut() {
+                ball.set_radius(stats.radius);
+            }
+
+            merged.send(EnemyMerged {
+                survivor: survivor_entity,
+                absorbed: absorbed_entity,
+                level: outcome.level,
+            });
+            absorbed.insert(absorbed_entity);
+            commands.entity(absorbed_entity).despawn_recursive();
+        }
+    }
+}
+
+/// Pushes the enemies around a merge away from it, harder the closer they are.
+fn knock_back_on_merge(
+    config: Res<GameConfig>,
+    mut merged: EventReader<EnemyMerged>,
+    survivors: Query<&Position, With<Merge>>,
+    mut enemies: Query<(Entity, &Position, &mut Knockback), With<Enemy>>,
+) {
+    for event in merged.iter() {
+        let center = match survivors.get(event.survivor) {
+            Ok(position) => position.current,
+            Err(_) => continue,
+        };
+        for (entity, position, mut knockback) in &mut enemies {
+            let offset = position.current - center;
+            let distance = offset.length();
+            if entity == event.survivor || distance >= config.merge_knockback_radius {
+                continue;
+            }
+            knockback.push(
+                offset.normalize_or_zero()
+                    * config.merge_knockback
+                    * (1. - distance / config.merge_knockback_radius),
+            );
+        }
+    }
+}
+
+fn insert_merge_flash(mut commands: Commands, query: Query<Entity, Added<Merge>>) {
+    for entity in &query {
+        let mut timer = Timer::from_seconds(MERGE_FLASH_SECONDS, false);
+        timer.tick(timer.duration());
+        commands.entity(entity).insert(MergeFlash(timer));
+    }
+}
+
+/// Also runs for every enemy when the config is reloaded, so tuned levels show at once.
+fn apply_merge_visuals(
+    config: Res<GameConfig>,
+    mut query: Query<(&Merge, ChangeTrackers<Merge>, &mut AnimationTimer)>,
+) {
+    for (merge, merge_tracker, mut timer) in &mut query {
+        if !config.is_changed() && !merge_tracker.is_changed() {
+            continue;
+        }
+        if let Some(stats) = config.merge_levels.get(merge.level) {
+            timer.set_duration(std::time::Duration::from_secs_f32(stats.frame_duration));
+        }
+    }
+}
+
+fn flash_on_merge(
+    time: Res<Time>,
+    config: Res<GameConfig>,
+    mut merged: EventReader<EnemyMerged>,
+    mut query: Query<(&Merge, &mut MergeFlash, &mut TextureAtlasSprite)>,
+) {
+    for event in merged.iter() {
+        if let Ok((_, mut flash, _)) = query.get_mut(event.survivor) {
+            flash.0.reset();
+        }
+    }
+
+    for (merge, mut flash, mut sprite) in &mut query {
+        flash.0.tick(time.delta());
+        let [r, g, b, a] = config
+            .merge_levels
+            .get(merge.level)
+            .map_or([1.0; 4], |stats| stats.tint);
+        sprite.color = if flash.0.finished() {
+            Color::rgba(r, g, b, a)
+        } else {
+            Color::rgba(1.0, 1.0, 1.0, 0.5)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn higher_level_survives() {
+        assert_eq!(
+            MergeRule::Increment.resolve(2, 1, 5),
+            Some(MergeOutcome {
+                first_survives: true,
+                level: 3
+            })
+        );
+        assert_eq!(
+            MergeRule::Increment.resolve(0, 2, 5),
+            Some(MergeOutcome {
+                first_survives: false,
+                level: 3
+            })
+        );
+    }
+
+    #[test]
+    fn first_survives_a_tie() {
+        assert_eq!(
+            MergeRule::Increment.resolve(1, 1, 5),
+            Some(MergeOutcome {
+                first_survives: true,
+                level: 2
+            })
+        );
+    }
+
+    #[test]
+    fn sum_adds_the_absorbed_levels() {
+        assert_eq!(
+            MergeRule::Sum.resolve(2, 1, 10),
+            Some(MergeOutcome {
+                first_survives: true,
+                level: 4
+            })
+        );
+    }
+
+    #[test]
+    fn levels_are_capped() {
+        assert_eq!(
+            MergeRule::Sum.resolve(3, 3, 5),
+            Some(MergeOutcome {
+                first_survives: true,
+                level: 5
+            })
+        );
+    }
+
+    #[test]
+    fn nothing_merges_with_the_highest_level() {
+        assert_eq!(MergeRule::Increment.resolve(5, 0, 5), None);
+        assert_eq!(MergeRule::Sum.resolve(0, 5, 5), None);
+    }
+}
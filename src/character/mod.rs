@@ -1,11 +1,19 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, time::FixedTimesteps, transform::TransformSystem};
+use bevy_rapier2d::prelude::Velocity;
 
-use crate::game::GameState;
+use crate::{
+    game::{step_progress, GameState},
+    physics::{Knockback, PhysicsSchedule, PhysicsStage},
+};
 
-use self::enemy::Enemy;
+use self::{enemy::Enemy, player::PlayerPosition};
 
+pub mod archetype;
+pub mod director;
 pub mod enemy;
+pub mod merge;
 pub mod player;
+pub mod steering;
 
 #[derive(Component)]
 pub struct GameOver(pub bool);
@@ -19,30 +27,89 @@ struct AnimationTimer(Timer);
 #[derive(Component)]
 struct Speed(f32);
 
+/// Where physics put a character on the last two gameplay steps.
+///
+/// `Transform` is interpolated between the two every frame, so movement stays smooth at any frame rate.
+/// Characters move by velocity; setting `current` directly teleports the rigid body.
+#[derive(Component)]
+pub struct Position {
+    pub current: Vec2,
+    previous: Vec2,
+}
+
+impl Position {
+    pub fn new(position: Vec2) -> Self {
+        Position {
+            current: position,
+            previous: position,
+        }
+    }
+
+    /// Moves without interpolating from the old position.
+    pub fn teleport(&mut self, position: Vec2) {
+        self.current = position;
+        self.previous = position;
+    }
+
+    /// Takes the position physics ended the step at.
+    pub fn record(&mut self, position: Vec2) {
+        self.previous = self.current;
+        self.current = position;
+    }
+}
+
 pub struct CharacterPlugin;
 impl Plugin for CharacterPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(sprite_movement).add_system(sprite_flipping);
+        app.world
+            .resource_mut::<PhysicsSchedule>()
+            .0
+            .add_system_to_stage(PhysicsStage::Prepare, apply_velocity);
+        app.add_system_to_stage(
+            CoreStage::PostUpdate,
+            interpolate_transforms.before(TransformSystem::TransformPropagate),
+        )
+        .add_system(sprite_flipping);
     }
 }
 
-fn sprite_movement(
+/// Hands the wanted movement to rapier, which resolves it against walls and other bodies.
+fn apply_velocity(
     state: Res<State<GameState>>,
-    time: Res<Time>,
-    mut player: Query<(&MoveDirection, &mut Transform, &Speed), Without<Enemy>>,
-    mut enemies: Query<(&MoveDirection, &mut Transform, &Speed), With<Enemy>>,
+    mut player: Query<(&MoveDirection, &Speed, &Knockback, &mut Velocity), Without<Enemy>>,
+    mut enemies: Query<(&MoveDirection, &Speed, &Knockback, &mut Velocity), With<Enemy>>,
 ) {
-    for (movement, mut transform, speed) in &mut enemies {
-        let move_vector =
-            Vec3::new(movement.0.x, movement.0.y, 0.) * time.delta_seconds() * speed.0;
-        transform.translation += move_vector;
+    for (movement, speed, knockback, mut velocity) in &mut enemies {
+        velocity.linvel = if state.current() != &GameState::Paused {
+            movement.0 * speed.0 + knockback.0
+        } else {
+            Vec2::ZERO
+        };
+    }
+
+    for (movement, speed, knockback, mut velocity) in &mut player {
+        velocity.linvel = if let GameState::InGame = state.current() {
+            movement.0 * speed.0 + knockback.0
+        } else {
+            Vec2::ZERO
+        };
     }
+}
+
+fn interpolate_transforms(
+    timesteps: Res<FixedTimesteps>,
+    mut query: Query<(&Position, &mut Transform, Option<&mut PlayerPosition>)>,
+) {
+    let alpha = step_progress(&timesteps);
+
+    for (position, mut transform, player_position) in &mut query {
+        let translation = position.previous.lerp(position.current, alpha);
+        transform.translation.x = translation.x;
+        transform.translation.y = translation.y;
 
-    if let GameState::InGame = state.current() {
-        for (movement, mut transform, speed) in &mut player {
-            let move_vector =
-                Vec3::new(movement.0.x, movement.0.y, 0.) * time.delta_seconds() * speed.0;
-            transform.translation += move_vector;
+        if let Some(mut player_position) = player_position {
+            player_position.x = translation.x;
+            player_position.y = translation.y;
         }
     }
 }
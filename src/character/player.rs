@@ -1,17 +1,27 @@
 use bevy::{prelude::*, sprite::Anchor};
-use bevy_rapier2d::prelude::{Collider, GravityScale, LockedAxes, RigidBody};
+use bevy_rapier2d::prelude::{
+    Collider, GravityScale, LockedAxes, RigidBody, Sensor, SolverGroups, Velocity,
+};
 
 use crate::{
     animation::Anim,
-    game::GameState,
-    lighting::GlobalLight,
+    arena::Arena,
+    config::GameConfig,
+    events::{LanternToggled, PlayerDied},
+    game::{fixed_timestep, FixedUpdateStage, GameState, RestartGame},
+    input::{Action, Direction, KeyBindings},
+    lighting::{ConeLight2d, GlobalLight, LanternLight, PointLight2d},
+    physics::{Knockback, PLAYER_GROUP},
     rendering,
     text::{MainText, SubText},
 };
 
-use super::{enemy::Enemy, AnimationTimer, GameOver, MoveDirection, Speed};
+use super::{enemy::Enemy, AnimationTimer, GameOver, MoveDirection, Position, Speed};
 
 pub struct PlayerPlugin;
+pub struct PlayerSpritePlugin;
+
+struct PlayerAtlas(Handle<TextureAtlas>);
 
 #[derive(Component)]
 struct Locomotion;
@@ -42,49 +52,80 @@ pub struct Lantern(pub bool);
 #[derive(Component)]
 pub struct LanternTimer(pub Timer);
 
+/// Seconds of light left in the lantern. It drains while lit and the lantern goes out when empty.
+#[derive(Component)]
+pub struct LanternFuel {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl LanternFuel {
+    pub fn full(max: f32) -> Self {
+        LanternFuel { current: max, max }
+    }
+
+    pub fn fraction(&self) -> f32 {
+        if self.max > 0. {
+            (self.current / self.max).clamp(0., 1.)
+        } else {
+            0.
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current <= 0.
+    }
+}
+
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app.add_startup_system(setup)
             .add_system_set(
                 SystemSet::on_update(GameState::InGame)
                     .with_system(movement_input)
-                    .with_system(lantern_direction)
-                    .with_system(update_player_position),
+                    .with_system(lantern_direction),
             )
-            .add_system(sprite_animation)
             .add_system(lantern_toggle)
-            .add_system(lantern_extinguisher);
+            .add_system_to_stage(FixedUpdateStage, lantern_extinguisher)
+            .add_system(reset_player)
+            .add_system(move_to_start)
+            .add_system(die)
+            .add_system(apply_player_speed);
     }
 }
 
-fn setup(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-) {
-    let texture_handle = asset_server.load("character.png");
-    let texture_atlas = TextureAtlas::from_grid(texture_handle, Vec2::new(16f32, 16f32), 18, 1);
-    let texture_atlas_handle = texture_atlases.add(texture_atlas);
-    let transform = Transform::from_xyz(0., 0., 0.);
+impl Plugin for PlayerSpritePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup_sprite)
+            .add_system_to_stage(CoreStage::PostUpdate, insert_sprite)
+            .add_system(sprite_animation);
+    }
+}
+
+fn setup(mut commands: Commands, config: Res<GameConfig>, arena: Res<Arena>) {
+    let start = arena.player_start;
+    let transform = Transform::from_translation(start.extend(0.));
 
     commands
-        .spawn_bundle(SpriteSheetBundle {
-            sprite: TextureAtlasSprite {
-                anchor: Anchor::BottomCenter,
-                ..default()
-            },
-            texture_atlas: texture_atlas_handle,
+        .spawn_bundle(SpatialBundle {
             transform,
             ..default()
         })
         .insert(Name::new("Player"))
-        .insert(PlayerPosition { x: 0., y: 0. })
+        .insert(PlayerPosition {
+            x: start.x,
+            y: start.y,
+        })
+        .insert(Position::new(start))
         .insert(LockedAxes::ROTATION_LOCKED)
         .insert(GravityScale(0.))
         .insert(RigidBody::Dynamic)
         .insert(Collider::ball(2.5))
+        .insert(SolverGroups::new(PLAYER_GROUP, u32::MAX))
+        .insert(Velocity::zero())
+        .insert(Knockback::default())
         .insert(MoveDirection(Vec2::new(0., 0.)))
-        .insert(Speed(15.0))
+        .insert(Speed(config.player_speed))
         .insert(LightDirection(Vec2::new(0., 0.)))
         .insert(Locomotion)
         .insert(AnimationTimer(Timer::from_seconds(0.12, true)))
@@ -95,11 +136,60 @@ fn setup(
             idle: Anim::new(17, 17),
         })
         .insert(Lantern(false))
+        .insert(LanternFuel::full(config.lantern_fuel))
         .insert(MainText("In darkness you perish".to_owned()))
         .insert(SubText("".to_owned()))
         .insert(ScreenTextTimer(Timer::from_seconds(5.0, false)))
         .insert(GameOver(false))
-        .insert(rendering::OrderedZ);
+        .insert(rendering::OrderedZ)
+        .with_children(|parent| {
+            let color = Color::rgb(1.0, 0.9, 0.7);
+            parent
+                .spawn_bundle(SpatialBundle {
+                    transform: Transform::from_xyz(0., 8., 0.),
+                    ..default()
+                })
+                .insert(Name::new("Lantern"))
+                .insert(LanternLight)
+                .insert(ConeLight2d {
+                    radius: 40.,
+                    angle: 1.2,
+                    direction: Vec2::X,
+                    color,
+                    intensity: 0.,
+                })
+                .insert(PointLight2d {
+                    radius: 12.,
+                    color,
+                    intensity: 0.,
+                });
+        });
+}
+
+fn setup_sprite(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+) {
+    let texture_handle = asset_server.load("character.png");
+    let texture_atlas = TextureAtlas::from_grid(texture_handle, Vec2::new(16f32, 16f32), 18, 1);
+    commands.insert_resource(PlayerAtlas(texture_atlases.add(texture_atlas)));
+}
+
+fn insert_sprite(
+    mut commands: Commands,
+    query: Query<Entity, Added<PlayerPosition>>,
+    texture_atlas: Res<PlayerAtlas>,
+) {
+    for entity in &query {
+        commands
+            .entity(entity)
+            .insert(TextureAtlasSprite {
+                anchor: Anchor::BottomCenter,
+                ..default()
+            })
+            .insert(texture_atlas.0.clone());
+    }
 }
 
 fn sprite_animation(
@@ -139,37 +229,52 @@ fn sprite_animation(
 fn lantern_toggle(
     state: Res<State<GameState>>,
     input: Res<Input<KeyCode>>,
-    mut query: Query<&mut Lantern>,
+    bindings: Res<KeyBindings>,
+    mut query: Query<(&mut Lantern, &LanternFuel)>,
     light: Query<&GlobalLight>,
+    mut toggled: EventWriter<LanternToggled>,
 ) {
     match state.current() {
-        GameState::GameOver => (),
+        GameState::GameOver | GameState::Paused => (),
         _ => {
             let light = light.single();
 
-            if input.just_pressed(KeyCode::F) && !light.0 {
-                for mut lantern in &mut query {
+            if bindings.just_pressed(&input, Action::ToggleLantern) && !light.0 {
+                for (mut lantern, fuel) in &mut query {
+                    if !lantern.0 && fuel.is_empty() {
+                        continue;
+                    }
                     lantern.0 = !lantern.0;
+                    toggled.send(LanternToggled { lit: lantern.0 });
                 }
             }
         }
     }
 }
 
-fn lantern_direction(input: Res<Input<KeyCode>>, mut query: Query<&mut LightDirection>) {
+fn lantern_direction(
+    input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut query: Query<&mut LightDirection>,
+) {
     for mut vector in &mut query {
-        if input.any_pressed([KeyCode::W, KeyCode::S, KeyCode::A, KeyCode::D]) {
+        if bindings.any_pressed(&input, &Action::MOVE) {
             let mut x = 0f32;
             let mut y = 0f32;
-            if input.any_pressed([KeyCode::A, KeyCode::D]) {
-                x = if input.pressed(KeyCode::A) {
+            let (up, down) = (Action::Move(Direction::Up), Action::Move(Direction::Down));
+            let (left, right) = (
+                Action::Move(Direction::Left),
+                Action::Move(Direction::Right),
+            );
+            if bindings.any_pressed(&input, &[left, right]) {
+                x = if bindings.pressed(&input, left) {
                     -1f32
                 } else {
                     1f32
                 };
             }
-            if input.any_pressed([KeyCode::W, KeyCode::S]) {
-                y = if input.pressed(KeyCode::W) {
+            if bindings.any_pressed(&input, &[up, down]) {
+                y = if bindings.pressed(&input, up) {
                     1f32
                 } else {
                     -1f32
@@ -181,14 +286,23 @@ fn lantern_direction(input: Res<Input<KeyCode>>, mut query: Query<&mut LightDire
     }
 }
 
-fn lantern_extinguisher(time: Res<Time>, mut query: Query<(&mut Lantern, &mut LanternTimer)>) {
+fn lantern_extinguisher(
+    state: Res<State<GameState>>,
+    mut query: Query<(&mut Lantern, &mut LanternTimer)>,
+    mut toggled: EventWriter<LanternToggled>,
+) {
+    if let GameState::Paused = state.current() {
+        return;
+    }
+
     if let Ok((mut lantern, mut timer)) = query.get_single_mut() {
         if lantern.0 {
             if timer.0.just_finished() {
                 lantern.0 = false;
                 timer.0.reset();
+                toggled.send(LanternToggled { lit: false });
             } else {
-                timer.0.tick(time.delta());
+                timer.0.tick(fixed_timestep());
             }
         }
     }
@@ -196,44 +310,114 @@ fn lantern_extinguisher(time: Res<Time>, mut query: Query<(&mut Lantern, &mut La
 
 fn movement_input(
     input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
     mut movement_input: Query<&mut MoveDirection, Without<Enemy>>,
 ) {
+    // Read every frame rather than on key changes, so releases during a pause are not missed.
     for mut vector in &mut movement_input {
-        if input.any_just_released([KeyCode::W, KeyCode::A, KeyCode::S, KeyCode::D])
-            || input.any_just_pressed([KeyCode::W, KeyCode::A, KeyCode::S, KeyCode::D])
-        {
-            let x = {
-                let mut temp = 0.;
-                if input.pressed(KeyCode::A) {
-                    temp -= 1.;
-                }
-                if input.pressed(KeyCode::D) {
-                    temp += 1.;
-                }
-                temp
-            };
-            let y = {
-                let mut temp = 0.;
-                if input.pressed(KeyCode::S) {
-                    temp -= 1.;
-                }
+        vector.0 = bindings.movement(&input).normalize_or_zero();
+    }
+}
 
-                if input.pressed(KeyCode::W) {
-                    temp += 1.
-                }
-                temp
-            };
+fn die(mut commands: Commands, mut died: EventReader<PlayerDied>, config: Res<GameConfig>) {
+    for event in died.iter() {
+        commands
+            .entity(event.player)
+            .insert(Sensor)
+            .insert(LanternTimer(Timer::from_seconds(
+                config.game_over_lantern_duration,
+                false,
+            )));
+    }
+}
 
-            vector.0.x = x;
-            vector.0.y = y;
-            vector.0 = vector.0.normalize_or_zero();
-        }
+fn reset_player(
+    mut commands: Commands,
+    mut events: EventReader<RestartGame>,
+    mut query: Query<
+        (
+            Entity,
+            &mut Transform,
+            &mut PlayerPosition,
+            &mut Position,
+            &mut MoveDirection,
+            &mut LightDirection,
+            &mut Lantern,
+            &mut LanternFuel,
+            &mut MainText,
+            &mut SubText,
+            &mut ScreenTextTimer,
+            &mut GameOver,
+        ),
+        Without<Enemy>,
+    >,
+    config: Res<GameConfig>,
+    arena: Res<Arena>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    let start = arena.player_start;
+    for (
+        entity,
+        mut transform,
+        mut player_position,
+        mut position,
+        mut move_direction,
+        mut light_direction,
+        mut lantern,
+        mut fuel,
+        mut main,
+        mut sub,
+        mut timer,
+        mut game_over,
+    ) in &mut query
+    {
+        transform.translation = start.extend(0.);
+        player_position.x = start.x;
+        player_position.y = start.y;
+        position.teleport(start);
+        move_direction.0 = Vec2::ZERO;
+        light_direction.0 = Vec2::ZERO;
+        lantern.0 = false;
+        *fuel = LanternFuel::full(config.lantern_fuel);
+        main.0 = "In darkness you perish".to_owned();
+        sub.0 = "".to_owned();
+        timer.0.reset();
+        game_over.0 = false;
+        commands
+            .entity(entity)
+            .insert(Knockback::default())
+            .remove::<Sensor>()
+            .remove::<LanternTimer>();
     }
 }
 
-fn update_player_position(mut query: Query<(&mut PlayerPosition, &Transform)>) {
-    for (mut player_data, transform) in &mut query {
-        player_data.x = transform.translation.x;
-        player_data.y = transform.translation.y;
+/// Puts the player at the start of a level loaded before the round begins.
+fn move_to_start(
+    arena: Res<Arena>,
+    state: Res<State<GameState>>,
+    mut query: Query<(&mut Transform, &mut PlayerPosition, &mut Position)>,
+) {
+    if !arena.is_changed() || state.current() != &GameState::Prelude {
+        return;
+    }
+
+    let start = arena.player_start;
+    for (mut transform, mut player_position, mut position) in &mut query {
+        transform.translation.x = start.x;
+        transform.translation.y = start.y;
+        player_position.x = start.x;
+        player_position.y = start.y;
+        position.teleport(start);
+    }
+}
+
+fn apply_player_speed(config: Res<GameConfig>, mut query: Query<&mut Speed, With<PlayerPosition>>) {
+    if config.is_changed() {
+        for mut speed in &mut query {
+            speed.0 = config.player_speed;
+        }
     }
 }
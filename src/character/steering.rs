@@ -0,0 +1,291 @@
+//! Steering behaviours that enemies blend, with per-archetype weights, into their `MoveDirection`.
+
+use bevy::prelude::*;
+use rand::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    config::GameConfig,
+    game::{FixedUpdateStage, GameState, FIXED_TIMESTEP},
+    lighting::LightField,
+    rng::GameRng,
+};
+
+use super::{enemy::Enemy, player::PlayerPosition, MoveDirection, Position};
+
+/// How fast the wander heading turns, in radians per second at most.
+const WANDER_TURN_RATE: f32 = 4.;
+
+/// Distance between the samples used to find which way the light gets darker.
+const LIGHT_SAMPLE_DISTANCE: f32 = 2.;
+
+/// Weights of each steering behaviour. A weight of zero turns the behaviour off.
+#[derive(Component, Clone, Deserialize)]
+#[serde(default)]
+pub struct Steering {
+    /// Head for the target, or the player when there is none.
+    pub seek: f32,
+    /// Move towards darker ground. Light-fearing enemies are also slowed down by light.
+    pub flee_light: f32,
+    /// Keep away from enemies closer than `separation_radius`.
+    pub separation: f32,
+    pub separation_radius: f32,
+    /// Move towards the middle of the enemies within `neighbour_radius`.
+    pub cohesion: f32,
+    /// Move the same way as the enemies within `neighbour_radius`.
+    pub alignment: f32,
+    pub neighbour_radius: f32,
+    /// Drift along a slowly turning heading.
+    pub wander: f32,
+    /// Circle the target at `orbit_radius`.
+    pub orbit: f32,
+    pub orbit_radius: f32,
+    /// Set by behaviour systems to steer towards something other than the player.
+    #[serde(skip)]
+    pub target: Option<Vec2>,
+    #[serde(skip)]
+    wander_angle: f32,
+    #[serde(skip)]
+    clockwise: bool,
+}
+
+impl Default for Steering {
+    fn default() -> Self {
+        Steering {
+            seek: 1.,
+            flee_light: 0.,
+            separation: 0.,
+            separation_radius: 6.,
+            cohesion: 0.,
+            alignment: 0.,
+            neighbour_radius: 16.,
+            wander: 0.,
+            orbit: 0.,
+            orbit_radius: 20.,
+            target: None,
+            wander_angle: 0.,
+            clockwise: false,
+        }
+    }
+}
+
+impl Steering {
+    /// A copy of these weights with a random wander heading and orbit direction.
+    pub fn randomized<R: Rng + ?Sized>(&self, rng: &mut R) -> Self {
+        Steering {
+            wander_angle: rng.gen_range(0f32..std::f32::consts::TAU),
+            clockwise: rng.gen(),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub struct Steer;
+
+pub struct SteeringPlugin;
+
+impl Plugin for SteeringPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_to_stage(FixedUpdateStage, steer.label(Steer));
+    }
+}
+
+pub fn seek(position: Vec2, target: Vec2) -> Vec2 {
+    (target - position).normalize_or_zero()
+}
+
+/// Away from close neighbours, harder the closer they are.
+pub fn separation(position: Vec2, neighbours: impl Iterator<Item = Vec2>, radius: f32) -> Vec2 {
+    neighbours
+        .map(|neighbour| position - neighbour)
+        .filter(|offset| *offset != Vec2::ZERO && offset.length() < radius)
+        .map(|offset| offset.normalize() * (1. - offset.length() / radius))
+        .sum()
+}
+
+pub fn cohesion(position: Vec2, neighbours: impl Iterator<Item = Vec2>) -> Vec2 {
+    let (sum, count) = neighbours.fold((Vec2::ZERO, 0), |(sum, count), neighbour| {
+        (sum + neighbour, count + 1)
+    });
+    if count == 0 {
+        return Vec2::ZERO;
+    }
+    seek(position, sum / count as f32)
+}
+
+pub fn alignment(headings: impl Iterator<Item = Vec2>) -> Vec2 {
+    headings.sum::<Vec2>().normalize_or_zero()
+}
+
+pub fn wander(angle: f32) -> Vec2 {
+    Vec2::new(angle.cos(), angle.sin())
+}
+
+/// Around `target` at `radius`, pulled in or pushed out when off the circle.
+pub fn orbit(position: Vec2, target: Vec2, radius: f32, clockwise: bool) -> Vec2 {
+    let inwards = seek(position, target);
+    let tangent = if clockwise {
+        Vec2::new(-inwards.y, inwards.x)
+    } else {
+        Vec2::new(inwards.y, -inwards.x)
+    };
+    let off_circle = ((position.distance(target) - radius) / radius).clamp(-1., 1.);
+    (tangent + inwards * off_circle).normalize_or_zero()
+}
+
+/// Towards darker ground, scaled by how lit `position` is.
+pub fn flee_light(light_field: &LightField, position: Vec2) -> Vec2 {
+    let light = light_field.light_at(position);
+    if light <= 0. {
+        return Vec2::ZERO;
+    }
+
+    let gradient = Vec2::new(
+        light_field.light_at(position + Vec2::X * LIGHT_SAMPLE_DISTANCE)
+            - light_field.light_at(position - Vec2::X * LIGHT_SAMPLE_DISTANCE),
+        light_field.light_at(position + Vec2::Y * LIGHT_SAMPLE_DISTANCE)
+            - light_field.light_at(position - Vec2::Y * LIGHT_SAMPLE_DISTANCE),
+    );
+    -gradient.normalize_or_zero() * light
+}
+
+/// Every enemy's position and heading, then the neighbours of one enemy, reused every step.
+#[derive(Default)]
+struct Flock {
+    enemies: Vec<(Entity, Vec2, Vec2)>,
+    neighbours: Vec<(Vec2, Vec2)>,
+}
+
+fn steer(
+    mut buffers: Local<Flock>,
+    state: Res<State<GameState>>,
+    light_field: Res<LightField>,
+    config: Res<GameConfig>,
+    mut rng: ResMut<GameRng>,
+    mut enemies: Query<(Entity, &Position, &mut Steering, &mut MoveDirection), With<Enemy>>,
+    player: Query<&Position, (With<PlayerPosition>, Without<Enemy>)>,
+) {
+    if state.current() == &GameState::Paused {
+        return;
+    }
+
+    let player = match player.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let Flock {
+        enemies: flock,
+        neighbours,
+    } = &mut *buffers;
+    flock.clear();
+    flock.extend(
+        enemies
+            .iter()
+            .map(|(entity, position, _, direction)| (entity, position.current, direction.0)),
+    );
+
+    for (entity, position, mut steering, mut move_direction) in &mut enemies {
+        let position = position.current;
+        let light = light_field.light_at(position);
+        if light >= config.enemy_freeze_light {
+            move_direction.0 = Vec2::ZERO;
+            continue;
+        }
+
+        steering.wander_angle +=
+            rng.gen_range(-1f32..=1.) * WANDER_TURN_RATE * FIXED_TIMESTEP as f32;
+
+        let target = steering.target.unwrap_or(player.current);
+        let others = || {
+            flock
+                .iter()
+                .filter(move |(other, _, _)| *other != entity)
+                .map(|(_, other, heading)| (*other, *heading))
+        };
+        neighbours.clear();
+        neighbours.extend(
+            others().filter(|(other, _)| other.distance(position) < steering.neighbour_radius),
+        );
+
+        let mut direction = seek(position, target) * steering.seek
+            + flee_light(&light_field, position) * steering.flee_light
+            + separation(
+                position,
+                others().map(|(other, _)| other),
+                steering.separation_radius,
+            ) * steering.separation
+            + cohesion(position, neighbours.iter().map(|(other, _)| *other)) * steering.cohesion
+            + alignment(neighbours.iter().map(|(_, heading)| *heading)) * steering.alignment
+            + wander(steering.wander_angle) * steering.wander
+            + orbit(position, target, steering.orbit_radius, steering.clockwise) * steering.orbit;
+
+        if steering.flee_light > 0. {
+            direction *= 1.0 - config.enemy_light_slowdown * light;
+        }
+        move_direction.0 = direction.clamp_length_max(1.);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: Vec2, expected: Vec2) {
+        assert!(
+            actual.distance(expected) < 1e-4,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn seek_heads_straight_for_the_target() {
+        assert_near(seek(Vec2::new(1., 1.), Vec2::new(1., 5.)), Vec2::Y);
+        assert_near(seek(Vec2::ONE, Vec2::ONE), Vec2::ZERO);
+    }
+
+    #[test]
+    fn separation_pushes_harder_the_closer_neighbours_are() {
+        let close = separation(Vec2::ZERO, [Vec2::new(1., 0.)].into_iter(), 4.);
+        let far = separation(Vec2::ZERO, [Vec2::new(3., 0.)].into_iter(), 4.);
+
+        assert_near(close, Vec2::new(-0.75, 0.));
+        assert_near(far, Vec2::new(-0.25, 0.));
+        // Out of reach, or on top of each other
+        let ignored = [Vec2::new(0., 5.), Vec2::ZERO];
+        assert_near(separation(Vec2::ZERO, ignored.into_iter(), 4.), Vec2::ZERO);
+    }
+
+    #[test]
+    fn cohesion_heads_for_the_middle_of_the_neighbours() {
+        let neighbours = [Vec2::new(4., 2.), Vec2::new(4., -2.)];
+
+        assert_near(cohesion(Vec2::ZERO, neighbours.into_iter()), Vec2::X);
+        assert_near(cohesion(Vec2::ZERO, std::iter::empty()), Vec2::ZERO);
+    }
+
+    #[test]
+    fn orbit_circles_on_the_radius_and_returns_to_it() {
+        let target = Vec2::ZERO;
+
+        assert_near(orbit(Vec2::new(10., 0.), target, 10., false), Vec2::Y);
+        assert_near(orbit(Vec2::new(10., 0.), target, 10., true), -Vec2::Y);
+        // Twice the radius away, pulled in as hard as it goes around
+        assert_near(
+            orbit(Vec2::new(20., 0.), target, 10., false),
+            Vec2::new(-1., 1.).normalize(),
+        );
+    }
+
+    #[test]
+    fn flee_light_heads_for_darker_ground() {
+        let light_field = LightField::point_light(Vec2::ZERO, 20.);
+        let direction = flee_light(&light_field, Vec2::new(5., 0.));
+
+        assert_near(direction.normalize(), Vec2::X);
+        assert!((direction.length() - light_field.light_at(Vec2::new(5., 0.))).abs() < 1e-4);
+        assert_near(flee_light(&light_field, Vec2::new(30., 0.)), Vec2::ZERO);
+    }
+}
@@ -0,0 +1,318 @@
+use std::ops::Range;
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+
+use crate::{
+    character::{
+        director::DirectorConfig,
+        merge::{MergeLevel, MergeRule},
+    },
+    level::{BuiltInRock, WorldKind},
+};
+
+/// Gameplay tuning, loaded from `assets/game.config.ron`.
+///
+/// Systems read the `GameConfig` resource, which starts out with the defaults below and is replaced
+/// whenever the asset is loaded or hot-reloaded.
+#[derive(Clone, Deserialize, TypeUuid)]
+#[uuid = "6f0e9a4c-2f5e-4b8e-9d0a-4f1c3b7e2a91"]
+#[serde(default)]
+pub struct GameConfig {
+    /// Where rounds are played.
+    pub world: WorldKind,
+    /// Rocks of the arena played while no level has loaded. Levels place their own.
+    pub built_in_rocks: Vec<BuiltInRock>,
+    pub player_speed: f32,
+    pub camera_scale: f32,
+    pub game_over_camera_scale: f32,
+    /// Seconds the lantern keeps burning after game over.
+    pub game_over_lantern_duration: f32,
+    /// When, where and which enemies spawn.
+    pub director: DirectorConfig,
+    /// Seconds before a freshly spawned enemy can hurt the player or merge.
+    pub dangerous_after: f32,
+    /// Seconds of light in a full lantern.
+    pub lantern_fuel: f32,
+    /// Fraction of fuel below which the lantern flickers and dims.
+    pub lantern_low_fuel: f32,
+    /// Seconds of light restored by one pickup.
+    pub fuel_pickup_amount: f32,
+    /// Seconds between fuel pickups appearing while the global light is out.
+    pub fuel_pickup_interval: f32,
+    pub fuel_pickup_distance: Range<f32>,
+    pub max_fuel_pickups: usize,
+    /// Fraction of their speed enemies lose in full light, scaled by how lit they are.
+    pub enemy_light_slowdown: f32,
+    /// Enemies stop and vanish where the light reaches this level. The global light is 1.
+    pub enemy_freeze_light: f32,
+    pub merge_rule: MergeRule,
+    /// Stats per merge level, starting with freshly spawned enemies. The last entry is the cap.
+    pub merge_levels: Vec<MergeLevel>,
+    /// Speed enemies are pushed away from a merge with, fading out towards `merge_knockback_radius`.
+    pub merge_knockback: f32,
+    pub merge_knockback_radius: f32,
+    /// Score gained per second while the lantern is off. With the lantern lit it is one point per second.
+    pub dark_score_multiplier: f32,
+    /// A `ScoreMilestone` is sent every time the score passes a multiple of this.
+    pub score_milestone: f32,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            world: WorldKind::default(),
+            built_in_rocks: [
+                ((-30., 20.), (8., 8.)),
+                ((35., -15.), (12., 6.)),
+                ((10., 45.), (6., 10.)),
+                ((-25., -40.), (10., 10.)),
+                ((55., 30.), (8., 14.)),
+                ((-60., -5.), (14., 8.)),
+            ]
+            .into_iter()
+            .map(|(position, size)| BuiltInRock { position, size })
+            .collect(),
+            player_speed: 15.0,
+            camera_scale: 0.15,
+            game_over_camera_scale: 0.08,
+            game_over_lantern_duration: 5.0,
+            director: DirectorConfig::default(),
+            dangerous_after: 0.5,
+            lantern_fuel: 30.0,
+            lantern_low_fuel: 0.25,
+            fuel_pickup_amount: 10.0,
+            fuel_pickup_interval: 8.0,
+            fuel_pickup_distance: 20.0..50.0,
+            max_fuel_pickups: 3,
+            enemy_light_slowdown: 0.5,
+            enemy_freeze_light: 1.0,
+            merge_rule: MergeRule::Increment,
+            merge_levels: (0..6)
+                .map(|level| {
+                    let growth = 1.1f32.powi(level);
+                    let shade = 1.0 - level as f32 * 0.1;
+                    MergeLevel {
+                        speed_multiplier: 1.0,
+                        radius: 2.5 * growth,
+                        scale: growth,
+                        tint: [1.0, shade, shade, 1.0],
+                        frame_duration: 0.1 - level as f32 * 0.01,
+                    }
+                })
+                .collect(),
+            merge_knockback: 30.0,
+            merge_knockback_radius: 12.0,
+            dark_score_multiplier: 2.0,
+            score_milestone: 50.0,
+        }
+    }
+}
+
+impl GameConfig {
+    /// Finds the first value that would break the game, e.g. a negative duration or an empty range.
+    pub fn validate(&self) -> Result<(), String> {
+        let positive = [
+            ("camera_scale", self.camera_scale),
+            ("game_over_camera_scale", self.game_over_camera_scale),
+            ("lantern_fuel", self.lantern_fuel),
+            ("fuel_pickup_interval", self.fuel_pickup_interval),
+            ("merge_knockback_radius", self.merge_knockback_radius),
+            ("score_milestone", self.score_milestone),
+            ("director.start_interval", self.director.start_interval),
+            ("director.min_interval", self.director.min_interval),
+            // Spawns would land on the view's edge, or on the player without a view
+            ("director.offscreen_margin", self.director.offscreen_margin),
+        ];
+        if let Some((name, value)) = positive
+            .into_iter()
+            .find(|(_, value)| value.is_nan() || *value <= 0.)
+        {
+            return Err(format!("{} must be above 0, not {}", name, value));
+        }
+
+        let non_negative = [
+            ("player_speed", self.player_speed),
+            (
+                "game_over_lantern_duration",
+                self.game_over_lantern_duration,
+            ),
+            ("dangerous_after", self.dangerous_after),
+            ("lantern_low_fuel", self.lantern_low_fuel),
+            ("fuel_pickup_amount", self.fuel_pickup_amount),
+            ("merge_knockback", self.merge_knockback),
+            ("director.view_size width", self.director.view_size.0),
+            ("director.view_size height", self.director.view_size.1),
+        ];
+        if let Some((name, value)) = non_negative
+            .into_iter()
+            .find(|(_, value)| value.is_nan() || *value < 0.)
+        {
+            return Err(format!("{} must not be negative, not {}", name, value));
+        }
+
+        if self.fuel_pickup_distance.is_empty() {
+            return Err(format!(
+                "fuel_pickup_distance {:?} is empty",
+                self.fuel_pickup_distance
+            ));
+        }
+        if let Some(rock) = self
+            .built_in_rocks
+            .iter()
+            .find(|rock| !(rock.size.0 > 0. && rock.size.1 > 0.))
+        {
+            return Err(format!("built-in rock {:?} needs a size above 0", rock));
+        }
+        if self.merge_levels.is_empty() {
+            return Err("merge_levels needs at least one level".to_owned());
+        }
+        for (level, stats) in self.merge_levels.iter().enumerate() {
+            if [stats.radius, stats.scale, stats.frame_duration]
+                .into_iter()
+                .any(|value| value.is_nan() || value <= 0.)
+            {
+                return Err(format!(
+                    "merge level {} needs a radius, scale and frame duration above 0",
+                    level
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+struct GameConfigHandle(Handle<GameConfig>);
+
+#[derive(Default)]
+struct GameConfigLoader;
+
+impl AssetLoader for GameConfigLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let config: GameConfig = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(config));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["config.ron"]
+    }
+}
+
+pub struct ConfigPlugin;
+
+impl Plugin for ConfigPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<GameConfig>()
+            .init_asset_loader::<GameConfigLoader>()
+            .init_resource::<GameConfig>()
+            .add_startup_system(setup)
+            .add_system_to_stage(CoreStage::PreUpdate, apply_config);
+    }
+}
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(GameConfigHandle(asset_server.load("game.config.ron")));
+}
+
+fn apply_config(
+    mut events: EventReader<AssetEvent<GameConfig>>,
+    assets: Res<Assets<GameConfig>>,
+    handle: Res<GameConfigHandle>,
+    mut config: ResMut<GameConfig>,
+) {
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle: changed } | AssetEvent::Modified { handle: changed }
+                if *changed == handle.0 =>
+            {
+                if let Some(loaded) = assets.get(changed) {
+                    // A broken edit while the game runs keeps the config it had
+                    match loaded.validate() {
+                        Ok(()) => {
+                            *config = loaded.clone();
+                            info!("Loaded game config");
+                        }
+                        Err(error) => warn!("Ignoring game config: {}", error),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(GameConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_values_that_break_the_game() {
+        let broken = [
+            GameConfig {
+                fuel_pickup_interval: -1.,
+                ..default()
+            },
+            GameConfig {
+                fuel_pickup_distance: 50.0..20.0,
+                ..default()
+            },
+            GameConfig {
+                merge_knockback_radius: 0.,
+                ..default()
+            },
+            GameConfig {
+                director: DirectorConfig {
+                    min_interval: 0.,
+                    ..default()
+                },
+                ..default()
+            },
+            GameConfig {
+                director: DirectorConfig {
+                    view_size: (-192., 108.),
+                    ..default()
+                },
+                ..default()
+            },
+            GameConfig {
+                director: DirectorConfig {
+                    offscreen_margin: -4.,
+                    ..default()
+                },
+                ..default()
+            },
+            GameConfig {
+                merge_levels: Vec::new(),
+                ..default()
+            },
+            GameConfig {
+                built_in_rocks: vec![BuiltInRock {
+                    position: (0., 0.),
+                    size: (4., 0.),
+                }],
+                ..default()
+            },
+        ];
+
+        for config in broken {
+            assert!(config.validate().is_err());
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! Gameplay events emitted by the core systems, for UI, audio, camera and stats to react to.
+
+use bevy::prelude::*;
+
+/// The player was caught by a dangerous enemy. The player is already marked `GameOver`.
+pub struct PlayerDied {
+    pub player: Entity,
+    pub score: f32,
+}
+
+pub struct LanternToggled {
+    pub lit: bool,
+}
+
+/// The player picked up lantern fuel.
+pub struct FuelCollected {
+    pub position: Vec2,
+    pub amount: f32,
+}
+
+/// The global light went out, which starts the enemy waves.
+pub struct GlobalLightExtinguished;
+
+pub struct EnemySpawned {
+    pub enemy: Entity,
+    pub position: Vec2,
+}
+
+/// A freshly spawned enemy can now hurt the player.
+pub struct EnemyBecameDangerous {
+    pub enemy: Entity,
+    pub position: Vec2,
+}
+
+/// The score crossed a multiple of `GameConfig::score_milestone`.
+pub struct ScoreMilestone {
+    pub score: u32,
+}
+
+pub struct GameEventsPlugin;
+
+impl Plugin for GameEventsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PlayerDied>()
+            .add_event::<LanternToggled>()
+            .add_event::<FuelCollected>()
+            .add_event::<GlobalLightExtinguished>()
+            .add_event::<EnemySpawned>()
+            .add_event::<EnemyBecameDangerous>()
+            .add_event::<ScoreMilestone>();
+    }
+}
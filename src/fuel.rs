@@ -0,0 +1,215 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, render::texture::DEFAULT_IMAGE_HANDLE};
+use bevy_egui::{egui, EguiContext};
+use rand::prelude::*;
+
+use crate::{
+    arena::Arena,
+    character::{
+        player::{Lantern, LanternFuel, PlayerPosition},
+        Position,
+    },
+    config::GameConfig,
+    events::{FuelCollected, LanternToggled},
+    game::{fixed_timestep, FixedUpdateStage, GameState, RestartGame, FIXED_TIMESTEP},
+    lighting::{GlobalLight, PointLight2d},
+    rendering,
+    rng::GameRng,
+};
+
+/// How close the player has to get to pick up fuel.
+const PICKUP_RADIUS: f32 = 4.;
+
+#[derive(Component)]
+pub struct FuelPickup {
+    pub amount: f32,
+}
+
+#[derive(Component)]
+struct FuelSpawnTimer(Timer);
+
+pub struct FuelPlugin;
+
+impl Plugin for FuelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup)
+            .add_system_set_to_stage(
+                FixedUpdateStage,
+                SystemSet::on_update(GameState::InGame)
+                    .with_system(drain_fuel)
+                    .with_system(spawn_fuel_pickups)
+                    .with_system(collect_fuel_pickups),
+            )
+            .add_system(reset_fuel_pickups);
+    }
+}
+
+/// The fuel gauge and the pickup sprites.
+pub struct FuelHudPlugin;
+
+impl Plugin for FuelHudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_to_stage(CoreStage::PostUpdate, insert_sprite)
+            .add_system(show_fuel_gauge);
+    }
+}
+
+fn setup(mut commands: Commands, config: Res<GameConfig>) {
+    commands
+        .spawn()
+        .insert(Name::new("Fuel Spawner"))
+        .insert(FuelSpawnTimer(Timer::new(
+            Duration::from_secs_f32(config.fuel_pickup_interval),
+            true,
+        )));
+}
+
+fn drain_fuel(
+    mut query: Query<(&mut Lantern, &mut LanternFuel)>,
+    mut toggled: EventWriter<LanternToggled>,
+) {
+    for (mut lantern, mut fuel) in &mut query {
+        if !lantern.0 {
+            continue;
+        }
+
+        fuel.current = (fuel.current - FIXED_TIMESTEP as f32).max(0.);
+        if fuel.is_empty() {
+            lantern.0 = false;
+            toggled.send(LanternToggled { lit: false });
+        }
+    }
+}
+
+fn spawn_fuel_pickups(
+    mut commands: Commands,
+    global_light: Query<&GlobalLight>,
+    player: Query<&Position, With<PlayerPosition>>,
+    pickups: Query<(), With<FuelPickup>>,
+    mut timer_query: Query<&mut FuelSpawnTimer>,
+    mut rng: ResMut<GameRng>,
+    arena: Res<Arena>,
+    config: Res<GameConfig>,
+) {
+    if global_light.iter().any(|light| light.0) {
+        return;
+    }
+
+    let mut timer = timer_query.single_mut();
+    timer.0.tick(fixed_timestep());
+    if !timer.0.just_finished() || pickups.iter().count() >= config.max_fuel_pickups {
+        return;
+    }
+    timer
+        .0
+        .set_duration(Duration::from_secs_f32(config.fuel_pickup_interval));
+
+    for player in &player {
+        let angle: f32 = rng.gen_range(0f32..std::f32::consts::TAU);
+        let distance = rng.gen_range(config.fuel_pickup_distance.clone());
+        let position = player.current + Vec2::new(angle.cos(), angle.sin()) * distance;
+        if !arena.is_open(position) {
+            continue;
+        }
+        spawn_fuel_pickup(&mut commands, position, config.fuel_pickup_amount);
+    }
+}
+
+pub fn spawn_fuel_pickup(commands: &mut Commands, position: Vec2, amount: f32) -> Entity {
+    commands
+        .spawn_bundle(SpatialBundle {
+            transform: Transform::from_translation(position.extend(0.)),
+            ..default()
+        })
+        .insert(Name::new("Fuel"))
+        .insert(FuelPickup { amount })
+        .insert(PointLight2d {
+            radius: 8.,
+            color: Color::rgb(1.0, 0.7, 0.3),
+            intensity: 0.6,
+        })
+        .insert(rendering::OrderedZ)
+        .id()
+}
+
+fn collect_fuel_pickups(
+    mut commands: Commands,
+    mut player: Query<(&Position, &mut LanternFuel)>,
+    pickups: Query<(Entity, &Transform, &FuelPickup)>,
+    mut collected: EventWriter<FuelCollected>,
+) {
+    for (player, mut fuel) in &mut player {
+        for (entity, transform, pickup) in &pickups {
+            let position = transform.translation.truncate();
+            if player.current.distance(position) > PICKUP_RADIUS {
+                continue;
+            }
+
+            fuel.current = (fuel.current + pickup.amount).min(fuel.max);
+            collected.send(FuelCollected {
+                position,
+                amount: pickup.amount,
+            });
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+fn reset_fuel_pickups(
+    mut commands: Commands,
+    mut events: EventReader<RestartGame>,
+    pickups: Query<Entity, With<FuelPickup>>,
+    mut timer_query: Query<&mut FuelSpawnTimer>,
+    config: Res<GameConfig>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    for entity in &pickups {
+        commands.entity(entity).despawn_recursive();
+    }
+    for mut timer in &mut timer_query {
+        timer.0 = Timer::new(Duration::from_secs_f32(config.fuel_pickup_interval), true);
+    }
+}
+
+fn insert_sprite(mut commands: Commands, query: Query<Entity, Added<FuelPickup>>) {
+    for entity in &query {
+        commands
+            .entity(entity)
+            .insert(Sprite {
+                color: Color::rgb(1.0, 0.75, 0.35),
+                custom_size: Some(Vec2::new(3., 4.)),
+                ..default()
+            })
+            .insert(DEFAULT_IMAGE_HANDLE.typed::<Image>());
+    }
+}
+
+fn show_fuel_gauge(
+    state: Res<State<GameState>>,
+    mut egui_context: ResMut<EguiContext>,
+    query: Query<&LanternFuel>,
+    config: Res<GameConfig>,
+) {
+    match state.current() {
+        GameState::InGame | GameState::Paused => (),
+        _ => return,
+    }
+
+    for fuel in &query {
+        let color = if fuel.fraction() < config.lantern_low_fuel {
+            egui::Color32::RED
+        } else {
+            egui::Color32::GOLD
+        };
+        egui::Area::new("fuel")
+            .anchor(egui::Align2::LEFT_BOTTOM, (15., -15.))
+            .show(egui_context.ctx_mut(), |ui| {
+                ui.colored_label(color, "FUEL");
+                ui.add(egui::ProgressBar::new(fuel.fraction()).desired_width(200.));
+            });
+    }
+}
@@ -1,37 +1,178 @@
-use bevy::prelude::*;
-use bevy_kira_audio::{prelude::*, Audio};
+use std::time::Duration;
+
+use bevy::{
+    app::PluginGroupBuilder,
+    asset::AssetPlugin,
+    hierarchy::HierarchyPlugin,
+    input::InputPlugin,
+    prelude::*,
+    time::{FixedTimestep, FixedTimesteps},
+    transform::TransformPlugin,
+};
 
 use crate::{
+    arena::{ArenaPlugin, ArenaSpritePlugin},
+    audio::AudioManagerPlugin,
     camera::follow_camera::FollowCameraPlugin,
-    character::{enemy::EnemyPlugin, player::PlayerPlugin, CharacterPlugin},
-    lighting::LightingPlugin,
-    text::TextPlugin,
+    character::{
+        archetype::ArchetypePlugin,
+        director::DirectorPlugin,
+        enemy::{EnemyPlugin, EnemySpritePlugin},
+        merge::{MergePlugin, MergeSpritePlugin},
+        player::{PlayerPlugin, PlayerSpritePlugin},
+        steering::SteeringPlugin,
+        CharacterPlugin,
+    },
+    config::ConfigPlugin,
+    events::{GameEventsPlugin, PlayerDied},
+    fuel::{FuelHudPlugin, FuelPlugin},
+    highscore::HighScorePlugin,
+    input::{InputActionsPlugin, RebindingPlugin},
+    level::LevelPlugin,
+    lighting::{LightingMaskPlugin, LightingPlugin},
+    music::AdaptiveMusicPlugin,
+    obstacle::ObstacleSpritePlugin,
+    pause::{PauseMenuPlugin, PausePlugin},
+    physics::PhysicsPlugin,
+    replay::ReplayPlugin,
+    rng::RngPlugin,
+    sfx::SoundEffectsPlugin,
+    text::{TextDisplayPlugin, TextPlugin},
 };
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum GameState {
     Prelude,
     InGame,
+    /// Pushed on top of `InGame`, so gameplay systems stop until it is popped again.
+    Paused,
     GameOver,
 }
 
+/// Length of one gameplay step in seconds, independent of the frame rate.
+pub const FIXED_TIMESTEP: f64 = 1.0 / 60.0;
+pub const FIXED_TIMESTEP_LABEL: &str = "gameplay";
+
+/// Runs gameplay systems zero or more times per frame in steps of `FIXED_TIMESTEP`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, StageLabel)]
+pub struct FixedUpdateStage;
+
+pub fn fixed_timestep() -> Duration {
+    Duration::from_secs_f64(FIXED_TIMESTEP)
+}
+
+/// How far this frame is past the last gameplay step, from 0 to 1, to interpolate positions by.
+pub fn step_progress(timesteps: &FixedTimesteps) -> f32 {
+    timesteps
+        .get(FIXED_TIMESTEP_LABEL)
+        .map_or(1.0, |step| step.overstep_percentage() as f32)
+}
+
+/// Sent to start a new round: every plugin resets its entities and the game returns to the prelude.
+pub struct RestartGame;
+
+pub struct GamePlugin;
+impl Plugin for GamePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_stage_after(
+            CoreStage::Update,
+            FixedUpdateStage,
+            SystemStage::parallel().with_run_criteria(
+                FixedTimestep::step(FIXED_TIMESTEP).with_label(FIXED_TIMESTEP_LABEL),
+            ),
+        )
+        .add_state(GameState::Prelude)
+        .add_state_to_stage(FixedUpdateStage, GameState::Prelude)
+        .add_state_to_stage(CoreStage::PostUpdate, GameState::Prelude)
+        .add_event::<RestartGame>()
+        .add_system(restart_game)
+        .add_system(game_over_on_death);
+    }
+}
+
+fn restart_game(mut events: EventReader<RestartGame>, mut state: ResMut<State<GameState>>) {
+    if events.iter().count() > 0 && state.current() != &GameState::Prelude {
+        // Replace rather than set, so a restart from the pause menu also drops the paused `InGame`.
+        state
+            .replace(GameState::Prelude)
+            .expect("State must be added to the game at this point.");
+    }
+}
+
+fn game_over_on_death(mut died: EventReader<PlayerDied>, mut state: ResMut<State<GameState>>) {
+    if died.iter().count() > 0 && state.current() == &GameState::InGame {
+        state
+            .set(GameState::GameOver)
+            .expect("State must be added to the game at this point.");
+    }
+}
+
+/// The full game: simulation, sprites, camera and UI.
+///
+/// Expects `DefaultPlugins`, `AudioPlugin` and `EguiPlugin` to be added already.
 pub struct ContourPlugins;
 
 impl PluginGroup for ContourPlugins {
-    fn build(&mut self, group: &mut bevy::app::PluginGroupBuilder) {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        add_simulation_plugins(group);
         group
-            .add(CharacterPlugin)
-            .add(PlayerPlugin)
+            .add(ArenaSpritePlugin)
+            .add(PlayerSpritePlugin)
+            .add(EnemySpritePlugin)
+            .add(MergeSpritePlugin)
+            .add(LightingMaskPlugin)
+            .add(ObstacleSpritePlugin)
+            .add(FuelHudPlugin)
             .add(FollowCameraPlugin)
-            .add(EnemyPlugin)
-            .add(LightingPlugin)
-            .add(TextPlugin);
+            .add(TextDisplayPlugin)
+            .add(HighScorePlugin)
+            .add(PauseMenuPlugin)
+            .add(RebindingPlugin)
+            .add(AudioManagerPlugin)
+            .add(AdaptiveMusicPlugin)
+            .add(SoundEffectsPlugin);
+    }
+}
+
+/// The gameplay loop on top of `MinimalPlugins`, without window, rendering, egui or audio.
+///
+/// Input is read from `Input<KeyCode>` through the default `KeyBindings`, so simulations drive the
+/// game by sending `KeyboardInput` events.
+pub struct ContourHeadlessPlugins;
+
+impl PluginGroup for ContourHeadlessPlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        MinimalPlugins.build(group);
+        group
+            .add(TransformPlugin)
+            .add(HierarchyPlugin)
+            .add(InputPlugin)
+            .add(AssetPlugin);
+        add_simulation_plugins(group);
     }
 }
 
-pub fn play_audio_system(asset_server: Res<AssetServer>, audio: Res<Audio>) {
-    audio
-        .play(asset_server.load("bgm.wav"))
-        .looped()
-        .with_volume(0.75);
+fn add_simulation_plugins(group: &mut PluginGroupBuilder) {
+    group
+        .add(GamePlugin)
+        .add(GameEventsPlugin)
+        .add(ConfigPlugin)
+        .add(RngPlugin)
+        .add(InputActionsPlugin)
+        .add(ReplayPlugin)
+        // Before every plugin that adds systems to the physics schedule
+        .add(PhysicsPlugin)
+        .add(CharacterPlugin)
+        .add(PlayerPlugin)
+        .add(EnemyPlugin)
+        .add(ArchetypePlugin)
+        .add(DirectorPlugin)
+        .add(SteeringPlugin)
+        .add(MergePlugin)
+        .add(LightingPlugin)
+        .add(ArenaPlugin)
+        .add(LevelPlugin)
+        .add(FuelPlugin)
+        .add(TextPlugin)
+        .add(PausePlugin);
 }
@@ -0,0 +1,246 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    character::player::Lantern,
+    events::PlayerDied,
+    game::{FixedUpdateStage, GameState, RestartGame, FIXED_TIMESTEP},
+    replay::InputReplay,
+    rng::GameRng,
+    storage,
+};
+
+const MAX_HIGH_SCORES: usize = 10;
+const STORAGE_KEY: &str = "highscores";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HighScoreEntry {
+    pub score: f32,
+    /// Seconds since the unix epoch.
+    pub date: u64,
+    pub seed: u64,
+    pub survival_time: f32,
+    pub lantern_time: f32,
+}
+
+/// The best `MAX_HIGH_SCORES` rounds, highest score first.
+#[derive(Default, Serialize, Deserialize)]
+pub struct HighScores {
+    pub entries: Vec<HighScoreEntry>,
+    /// Rank of the entry added by the last round, if it made the table.
+    #[serde(skip)]
+    pub latest: Option<usize>,
+}
+
+impl HighScores {
+    pub fn insert(&mut self, entry: HighScoreEntry) -> Option<usize> {
+        let rank = self
+            .entries
+            .iter()
+            .position(|existing| entry.score > existing.score)
+            .unwrap_or(self.entries.len());
+
+        self.latest = if rank < MAX_HIGH_SCORES {
+            self.entries.insert(rank, entry);
+            self.entries.truncate(MAX_HIGH_SCORES);
+            Some(rank)
+        } else {
+            None
+        };
+        self.latest
+    }
+}
+
+/// Time spent in the current round, in seconds.
+#[derive(Default)]
+pub struct RoundStats {
+    pub survival_time: f32,
+    pub lantern_time: f32,
+}
+
+pub struct HighScorePlugin;
+
+impl Plugin for HighScorePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(storage::load::<HighScores>(STORAGE_KEY).unwrap_or_default())
+            .init_resource::<RoundStats>()
+            .add_system_set_to_stage(
+                FixedUpdateStage,
+                SystemSet::on_update(GameState::InGame).with_system(track_round_stats),
+            )
+            .add_system(record_high_score)
+            .add_system_set(SystemSet::on_update(GameState::GameOver).with_system(show_high_scores))
+            .add_system(reset_round_stats);
+    }
+}
+
+fn track_round_stats(mut stats: ResMut<RoundStats>, lantern: Query<&Lantern>) {
+    stats.survival_time += FIXED_TIMESTEP as f32;
+    if lantern.iter().any(|lantern| lantern.0) {
+        stats.lantern_time += FIXED_TIMESTEP as f32;
+    }
+}
+
+fn record_high_score(
+    mut died: EventReader<PlayerDied>,
+    mut high_scores: ResMut<HighScores>,
+    stats: Res<RoundStats>,
+    rng: Res<GameRng>,
+    replay: Option<Res<InputReplay>>,
+) {
+    // Replayed rounds were already recorded when they were played
+    if replay.is_some() {
+        return;
+    }
+
+    for event in died.iter() {
+        high_scores.insert(HighScoreEntry {
+            score: event.score.round(),
+            date: storage::now(),
+            seed: rng.seed(),
+            survival_time: stats.survival_time,
+            lantern_time: stats.lantern_time,
+        });
+        storage::save(STORAGE_KEY, &*high_scores);
+    }
+}
+
+fn reset_round_stats(mut events: EventReader<RestartGame>, mut stats: ResMut<RoundStats>) {
+    if events.iter().count() > 0 {
+        *stats = RoundStats::default();
+    }
+}
+
+fn show_high_scores(mut egui_context: ResMut<EguiContext>, high_scores: Res<HighScores>) {
+    egui::Area::new("high_scores")
+        .anchor(egui::Align2::CENTER_BOTTOM, (0., -40.))
+        .show(egui_context.ctx_mut(), |ui| {
+            egui::Grid::new("high_score_table")
+                .spacing((24., 8.))
+                .show(ui, |ui| {
+                    for header in ["#", "score", "time", "lantern", "date", "seed"] {
+                        ui.colored_label(egui::Color32::GRAY, header);
+                    }
+                    ui.end_row();
+
+                    for (rank, entry) in high_scores.entries.iter().enumerate() {
+                        let color = if high_scores.latest == Some(rank) {
+                            egui::Color32::GOLD
+                        } else {
+                            egui::Color32::WHITE
+                        };
+                        ui.colored_label(color, format!("{}", rank + 1));
+                        ui.colored_label(color, format!("{}", entry.score));
+                        ui.colored_label(color, format!("{:.0}s", entry.survival_time));
+                        ui.colored_label(color, format!("{:.0}s", entry.lantern_time));
+                        ui.colored_label(color, format_date(entry.date));
+                        ui.colored_label(color, format!("{}", entry.seed));
+                        ui.end_row();
+                    }
+                });
+        });
+}
+
+/// Formats seconds since the unix epoch as `YYYY-MM-DD` (UTC).
+fn format_date(seconds: u64) -> String {
+    // Howard Hinnant's civil-from-days algorithm.
+    let days = (seconds / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(score: f32) -> HighScoreEntry {
+        HighScoreEntry {
+            score,
+            date: 0,
+            seed: 0,
+            survival_time: 0.,
+            lantern_time: 0.,
+        }
+    }
+
+    fn scores(high_scores: &HighScores) -> Vec<f32> {
+        high_scores
+            .entries
+            .iter()
+            .map(|entry| entry.score)
+            .collect()
+    }
+
+    #[test]
+    fn insert_keeps_the_highest_score_first() {
+        let mut high_scores = HighScores::default();
+
+        assert_eq!(high_scores.insert(entry(10.)), Some(0));
+        assert_eq!(high_scores.insert(entry(30.)), Some(0));
+        assert_eq!(high_scores.insert(entry(20.)), Some(1));
+        assert_eq!(scores(&high_scores), vec![30., 20., 10.]);
+        assert_eq!(high_scores.latest, Some(1));
+    }
+
+    #[test]
+    fn insert_keeps_only_the_table_size() {
+        let mut high_scores = HighScores::default();
+        for score in 0..MAX_HIGH_SCORES {
+            high_scores.insert(entry(score as f32 + 1.));
+        }
+
+        assert_eq!(high_scores.insert(entry(100.)), Some(0));
+        assert_eq!(high_scores.entries.len(), MAX_HIGH_SCORES);
+        assert_eq!(
+            high_scores.entries.last().map(|entry| entry.score),
+            Some(2.)
+        );
+
+        assert_eq!(high_scores.insert(entry(0.5)), None);
+        assert_eq!(high_scores.latest, None);
+        assert_eq!(high_scores.entries.len(), MAX_HIGH_SCORES);
+    }
+
+    #[test]
+    fn a_tie_at_the_cutoff_does_not_make_the_table() {
+        let mut high_scores = HighScores::default();
+        for _ in 0..MAX_HIGH_SCORES {
+            high_scores.insert(entry(5.));
+        }
+
+        assert_eq!(high_scores.insert(entry(5.)), None);
+        assert_eq!(scores(&high_scores), vec![5.; MAX_HIGH_SCORES]);
+    }
+
+    #[test]
+    fn a_tie_ranks_below_the_earlier_round() {
+        let mut high_scores = HighScores::default();
+        high_scores.insert(entry(10.));
+        high_scores.insert(entry(5.));
+
+        assert_eq!(high_scores.insert(entry(10.)), Some(1));
+    }
+
+    #[test]
+    fn formats_dates_in_utc() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(86_399), "1970-01-01");
+        assert_eq!(format_date(951_782_400), "2000-02-29");
+        assert_eq!(format_date(1_709_164_800), "2024-02-29");
+        assert_eq!(format_date(1_735_689_599), "2024-12-31");
+    }
+}
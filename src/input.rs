@@ -0,0 +1,299 @@
+//! Input actions bound to keys, so systems ask whether `Action::ToggleLantern` was pressed rather
+//! than `F`. Bindings can be changed from the pause menu and are kept between sessions.
+
+use std::collections::HashMap;
+
+use bevy::{input::InputSystem, prelude::*};
+use bevy_egui::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::GameState,
+    replay::{InputReplay, RecordInput},
+    storage,
+};
+
+const STORAGE_KEY: &str = "bindings";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    Move(Direction),
+    ToggleLantern,
+    KillGlobalLight,
+    /// Steps through the prelude.
+    Confirm,
+    Restart,
+}
+
+impl Action {
+    /// Movement in the order its keys are listed, e.g. `<wasd>`.
+    pub const MOVE: [Action; 4] = [
+        Action::Move(Direction::Up),
+        Action::Move(Direction::Left),
+        Action::Move(Direction::Down),
+        Action::Move(Direction::Right),
+    ];
+
+    pub const ALL: [Action; 8] = [
+        Action::Move(Direction::Up),
+        Action::Move(Direction::Left),
+        Action::Move(Direction::Down),
+        Action::Move(Direction::Right),
+        Action::ToggleLantern,
+        Action::KillGlobalLight,
+        Action::Confirm,
+        Action::Restart,
+    ];
+
+    pub fn default_key(self) -> KeyCode {
+        match self {
+            Action::Move(Direction::Up) => KeyCode::W,
+            Action::Move(Direction::Down) => KeyCode::S,
+            Action::Move(Direction::Left) => KeyCode::A,
+            Action::Move(Direction::Right) => KeyCode::D,
+            Action::ToggleLantern => KeyCode::F,
+            Action::KillGlobalLight => KeyCode::G,
+            Action::Confirm => KeyCode::Space,
+            Action::Restart => KeyCode::R,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Action::Move(Direction::Up) => "move up",
+            Action::Move(Direction::Down) => "move down",
+            Action::Move(Direction::Left) => "move left",
+            Action::Move(Direction::Right) => "move right",
+            Action::ToggleLantern => "lantern",
+            Action::KillGlobalLight => "put out the light",
+            Action::Confirm => "confirm",
+            Action::Restart => "restart",
+        }
+    }
+}
+
+/// The key of every action. Actions missing from the map use their default key.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct KeyBindings {
+    keys: HashMap<Action, KeyCode>,
+}
+
+impl KeyBindings {
+    pub fn key(&self, action: Action) -> KeyCode {
+        self.keys
+            .get(&action)
+            .copied()
+            .unwrap_or_else(|| action.default_key())
+    }
+
+    /// Binds `key` to `action`. An action already bound to `key` gets the old key of `action`.
+    pub fn bind(&mut self, action: Action, key: KeyCode) {
+        let old = self.key(action);
+        if let Some(other) = Action::ALL
+            .into_iter()
+            .find(|other| *other != action && self.key(*other) == key)
+        {
+            self.keys.insert(other, old);
+        }
+        self.keys.insert(action, key);
+    }
+
+    pub fn pressed(&self, input: &Input<KeyCode>, action: Action) -> bool {
+        input.pressed(self.key(action))
+    }
+
+    pub fn just_pressed(&self, input: &Input<KeyCode>, action: Action) -> bool {
+        input.just_pressed(self.key(action))
+    }
+
+    pub fn any_pressed(&self, input: &Input<KeyCode>, actions: &[Action]) -> bool {
+        actions.iter().any(|action| self.pressed(input, *action))
+    }
+
+    /// Held movement keys as a vector with components of -1, 0 or 1, not normalized.
+    pub fn movement(&self, input: &Input<KeyCode>) -> Vec2 {
+        let axis = |negative, positive| {
+            let mut value = 0.;
+            if self.pressed(input, Action::Move(negative)) {
+                value -= 1.;
+            }
+            if self.pressed(input, Action::Move(positive)) {
+                value += 1.;
+            }
+            value
+        };
+        Vec2::new(
+            axis(Direction::Left, Direction::Right),
+            axis(Direction::Down, Direction::Up),
+        )
+    }
+
+    /// The keys of `actions` for on-screen prompts, e.g. `<wasd>` or `<up/left/down/right>`.
+    pub fn prompt(&self, actions: &[Action]) -> String {
+        let names: Vec<String> = actions
+            .iter()
+            .map(|action| key_name(self.key(*action)))
+            .collect();
+        let separator = if names.iter().all(|name| name.chars().count() == 1) {
+            ""
+        } else {
+            "/"
+        };
+        format!("<{}>", names.join(separator))
+    }
+}
+
+fn key_name(key: KeyCode) -> String {
+    format!("{:?}", key).to_lowercase()
+}
+
+/// The controls screen of the pause menu.
+#[derive(Default)]
+pub struct ControlsMenu {
+    pub open: bool,
+    /// Action whose new key is awaited.
+    waiting: Option<Action>,
+}
+
+impl ControlsMenu {
+    pub fn show(&mut self, ui: &mut egui::Ui, bindings: &KeyBindings) {
+        ui.colored_label(egui::Color32::WHITE, "CONTROLS");
+        ui.add_space(20.);
+        for action in Action::ALL {
+            let key = if self.waiting == Some(action) {
+                "press a key".to_owned()
+            } else {
+                key_name(bindings.key(action))
+            };
+            if ui.button(format!("{}: {}", action.name(), key)).clicked() {
+                self.waiting = Some(action);
+            }
+        }
+        ui.add_space(20.);
+        if ui.button("<esc> back").clicked() {
+            *self = ControlsMenu::default();
+        }
+    }
+}
+
+/// Key bindings with their defaults, for the simulation.
+pub struct InputActionsPlugin;
+
+impl Plugin for InputActionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<KeyBindings>();
+    }
+}
+
+/// Stored bindings and the controls screen.
+pub struct RebindingPlugin;
+
+impl Plugin for RebindingPlugin {
+    fn build(&self, app: &mut App) {
+        // Replays bring the bindings they were recorded with
+        if !app.world.contains_resource::<InputReplay>() {
+            if let Some(bindings) = storage::load::<KeyBindings>(STORAGE_KEY) {
+                app.insert_resource(bindings);
+            }
+        }
+
+        app.init_resource::<ControlsMenu>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                capture_key.after(InputSystem).before(RecordInput),
+            )
+            .add_system(close_controls_on_resume)
+            .add_system(save_bindings);
+    }
+}
+
+/// Binds the next key pressed on the controls screen.
+///
+/// Every key pressed while the screen is open is hidden from everything else, so it cannot also
+/// resume, restart or quit from the pause menu.
+fn capture_key(
+    mut menu: ResMut<ControlsMenu>,
+    mut input: ResMut<Input<KeyCode>>,
+    mut bindings: ResMut<KeyBindings>,
+) {
+    if !menu.open {
+        return;
+    }
+    let pressed: Vec<KeyCode> = input.get_just_pressed().copied().collect();
+
+    if let Some(&key) = pressed.first() {
+        match menu.waiting.take() {
+            Some(_) if key == KeyCode::Escape => (),
+            Some(action) => bindings.bind(action, key),
+            None if key == KeyCode::Escape => menu.open = false,
+            None => (),
+        }
+    }
+    for key in pressed {
+        input.reset(key);
+    }
+}
+
+fn close_controls_on_resume(state: Res<State<GameState>>, mut menu: ResMut<ControlsMenu>) {
+    if state.is_changed() && state.current() != &GameState::Paused && menu.open {
+        *menu = ControlsMenu::default();
+    }
+}
+
+fn save_bindings(bindings: Res<KeyBindings>, replay: Option<Res<InputReplay>>) {
+    // Bindings changed by a replay are not the player's
+    if bindings.is_changed() && !bindings.is_added() && replay.is_none() {
+        storage::save(STORAGE_KEY, &*bindings);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind_swaps_with_the_action_already_on_the_key() {
+        let mut bindings = KeyBindings::default();
+        bindings.bind(Action::ToggleLantern, KeyCode::G);
+
+        assert_eq!(bindings.key(Action::ToggleLantern), KeyCode::G);
+        assert_eq!(bindings.key(Action::KillGlobalLight), KeyCode::F);
+    }
+
+    #[test]
+    fn bind_to_a_free_key_leaves_other_actions() {
+        let mut bindings = KeyBindings::default();
+        bindings.bind(Action::Restart, KeyCode::Back);
+
+        assert_eq!(bindings.key(Action::Restart), KeyCode::Back);
+        for action in Action::ALL {
+            if action != Action::Restart {
+                assert_eq!(bindings.key(action), action.default_key());
+            }
+        }
+    }
+
+    #[test]
+    fn prompt_joins_single_letters() {
+        let bindings = KeyBindings::default();
+
+        assert_eq!(bindings.prompt(&Action::MOVE), "<wasd>");
+        assert_eq!(bindings.prompt(&[Action::Restart]), "<r>");
+    }
+
+    #[test]
+    fn prompt_separates_longer_key_names() {
+        let mut bindings = KeyBindings::default();
+        bindings.bind(Action::Move(Direction::Up), KeyCode::Up);
+
+        assert_eq!(bindings.prompt(&Action::MOVE), "<up/a/s/d>");
+    }
+}
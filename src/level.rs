@@ -0,0 +1,539 @@
+//! Levels made in Tiled and saved as JSON maps (`.tmj`) with CSV tile layers, using `tiles.png`
+//! as their tileset.
+//!
+//! The tile layer named `floor` picks floor textures; every other tile layer places walls and props.
+//! Objects are recognised by their class:
+//!
+//! - `player_spawn`: a point where the player starts.
+//! - `enemy_spawn`: a rectangle enemies spawn in. Without any, they spawn anywhere out of sight.
+//! - `light`: a point light, with optional `radius`, `intensity` and `color` properties.
+//! - `fuel`: a fuel pickup placed at the start of every round, with an optional `amount` property.
+//! - `rock`: a rectangle blocking movement and light.
+//!
+//! `GameConfig::world` picks the level, or the endless world of `cave` instead.
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+
+use crate::{
+    arena::{Arena, SpawnZone, Tile, TILE_SIZE},
+    cave::Cave,
+    config::GameConfig,
+    fuel::{spawn_fuel_pickup, FuelPickup},
+    game::{GameState, RestartGame},
+    lighting::PointLight2d,
+    obstacle::spawn_rock,
+    rng::GameRng,
+};
+
+/// Tile ids in Tiled keep flip flags in their top bits.
+const TILED_FLIP_FLAGS: u32 = 0xe000_0000;
+
+/// Something placed in the level that is spawned anew every round.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LevelObject {
+    Light {
+        position: Vec2,
+        radius: f32,
+        color: Color,
+        intensity: f32,
+    },
+    Fuel {
+        position: Vec2,
+        amount: Option<f32>,
+    },
+    Rock {
+        position: Vec2,
+        size: Vec2,
+    },
+}
+
+/// A level loaded from a Tiled map.
+#[derive(Clone, TypeUuid)]
+#[uuid = "8d4e2b71-9a3c-4f05-b6e8-15c7a0d93f26"]
+pub struct Level {
+    pub arena: Arena,
+    pub objects: Vec<LevelObject>,
+}
+
+/// Where rounds are played, part of `GameConfig`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub enum WorldKind {
+    /// A Tiled map, by asset path.
+    Level(String),
+    /// The endless procedural world, seeded from `GameRng`.
+    Cave,
+}
+
+impl Default for WorldKind {
+    fn default() -> Self {
+        WorldKind::Level("levels/arena.tmj".to_owned())
+    }
+}
+
+/// A rock of the built-in arena, part of `GameConfig`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct BuiltInRock {
+    /// Centre and size in world units.
+    pub position: (f32, f32),
+    pub size: (f32, f32),
+}
+
+impl BuiltInRock {
+    fn object(&self) -> LevelObject {
+        LevelObject::Rock {
+            position: self.position.into(),
+            size: self.size.into(),
+        }
+    }
+}
+
+/// Objects of the current level. The built-in arena's rocks until a level has loaded, none for
+/// the endless world.
+#[derive(Default)]
+pub struct LevelObjects(pub Vec<LevelObject>);
+
+/// Marks entities spawned from `LevelObjects`.
+#[derive(Component)]
+struct FromLevel;
+
+/// The level being played, if any.
+#[derive(Default)]
+struct LevelHandle(Option<Handle<Level>>);
+
+#[derive(Deserialize)]
+struct TiledMap {
+    width: usize,
+    height: usize,
+    tilewidth: f32,
+    layers: Vec<TiledLayer>,
+    #[serde(default)]
+    tilesets: Vec<TiledTileset>,
+}
+
+#[derive(Deserialize)]
+struct TiledTileset {
+    firstgid: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum TiledLayer {
+    Tilelayer {
+        name: String,
+        #[serde(default)]
+        data: Vec<u32>,
+    },
+    Objectgroup {
+        #[serde(default)]
+        objects: Vec<TiledObject>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct TiledObject {
+    /// Called `type` before Tiled 1.9.
+    #[serde(default, alias = "type")]
+    class: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    #[serde(default)]
+    properties: Vec<TiledProperty>,
+}
+
+#[derive(Deserialize)]
+struct TiledProperty {
+    name: String,
+    value: serde_json::Value,
+}
+
+impl TiledObject {
+    fn property(&self, name: &str) -> Option<&serde_json::Value> {
+        self.properties
+            .iter()
+            .find(|property| property.name == name)
+            .map(|property| &property.value)
+    }
+
+    fn float(&self, name: &str) -> Option<f32> {
+        self.property(name)
+            .and_then(|value| value.as_f64())
+            .map(|value| value as f32)
+    }
+
+    fn color(&self, name: &str) -> Option<Color> {
+        self.property(name)
+            .and_then(|value| value.as_str())
+            .and_then(parse_tiled_color)
+    }
+}
+
+/// Parses Tiled's `#AARRGGBB` or `#RRGGBB` colours.
+fn parse_tiled_color(color: &str) -> Option<Color> {
+    let hex = color.strip_prefix('#')?;
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    let channel = |shift: u32| ((value >> shift) & 0xff) as f32 / 255.;
+    match hex.len() {
+        6 => Some(Color::rgb(channel(16), channel(8), channel(0))),
+        8 => Some(Color::rgba(
+            channel(16),
+            channel(8),
+            channel(0),
+            channel(24),
+        )),
+        _ => None,
+    }
+}
+
+impl TiledMap {
+    fn into_level(self) -> Result<Level, bevy::asset::Error> {
+        let origin = -Vec2::new(self.width as f32, self.height as f32) * TILE_SIZE / 2.;
+        let mut arena = Arena::new(self.width, self.height, origin);
+        let first_gid = self.tilesets.first().map_or(1, |tileset| tileset.firstgid);
+        // Object coordinates are in the map's pixels, from the top left corner
+        let scale = TILE_SIZE / self.tilewidth;
+        let map_height = self.height as f32 * TILE_SIZE;
+        let to_world = |x: f32, y: f32| origin + Vec2::new(x * scale, map_height - y * scale);
+
+        let mut objects = Vec::new();
+        for layer in self.layers {
+            match layer {
+                TiledLayer::Tilelayer { name, data } => {
+                    if data.len() != self.width * self.height {
+                        return Err(bevy::asset::Error::msg(format!(
+                            "Tile layer {} has {} tiles, expected {}",
+                            name,
+                            data.len(),
+                            self.width * self.height
+                        )));
+                    }
+                    for (i, gid) in data.into_iter().enumerate() {
+                        let gid = gid & !TILED_FLIP_FLAGS;
+                        if gid < first_gid {
+                            continue;
+                        }
+                        let texture = gid - first_gid;
+                        // Tiled rows run top to bottom
+                        let (x, y) = (i % self.width, self.height - 1 - i / self.width);
+                        if name == "floor" {
+                            arena.set_floor_texture(x, y, texture);
+                        } else {
+                            arena.set(x, y, Tile::from_texture(texture));
+                        }
+                    }
+                }
+                TiledLayer::Objectgroup { objects: layer } => {
+                    for object in layer {
+                        let position = to_world(object.x, object.y);
+                        match object.class.as_str() {
+                            "player_spawn" => arena.player_start = position,
+                            "enemy_spawn" => arena.spawn_zones.push(SpawnZone {
+                                min: to_world(object.x, object.y + object.height),
+                                max: to_world(object.x + object.width, object.y),
+                            }),
+                            "light" => objects.push(LevelObject::Light {
+                                position,
+                                radius: object.float("radius").unwrap_or(24.),
+                                color: object
+                                    .color("color")
+                                    .unwrap_or_else(|| Color::rgb(1.0, 0.75, 0.45)),
+                                intensity: object.float("intensity").unwrap_or(0.8),
+                            }),
+                            "fuel" => objects.push(LevelObject::Fuel {
+                                position,
+                                amount: object.float("amount"),
+                            }),
+                            "rock" => objects.push(LevelObject::Rock {
+                                position: to_world(
+                                    object.x + object.width / 2.,
+                                    object.y + object.height / 2.,
+                                ),
+                                size: Vec2::new(object.width, object.height) * scale,
+                            }),
+                            other => warn!("Ignoring level object of unknown class {:?}", other),
+                        }
+                    }
+                }
+                TiledLayer::Other => (),
+            }
+        }
+
+        Ok(Level { arena, objects })
+    }
+}
+
+#[derive(Default)]
+struct TiledLoader;
+
+impl AssetLoader for TiledLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let map: TiledMap = serde_json::from_slice(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(map.into_level()?));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tmj"]
+    }
+}
+
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<Level>()
+            .init_asset_loader::<TiledLoader>()
+            .init_resource::<LevelObjects>()
+            .init_resource::<LevelHandle>()
+            .add_system_to_stage(CoreStage::PreUpdate, choose_world)
+            .add_system_to_stage(CoreStage::PreUpdate, apply_level.after(choose_world))
+            .add_system(spawn_level_objects);
+    }
+}
+
+/// Switches worlds when the config asks for a different one, including at startup, and places the
+/// built-in arena's rocks while no level has loaded.
+///
+/// Like `apply_level`, waits for the prelude so a round is never played on a world changing under it.
+fn choose_world(
+    mut current: Local<Option<WorldKind>>,
+    mut config_changed: Local<bool>,
+    state: Res<State<GameState>>,
+    config: Res<GameConfig>,
+    asset_server: Res<AssetServer>,
+    assets: Res<Assets<Level>>,
+    rng: Res<GameRng>,
+    mut handle: ResMut<LevelHandle>,
+    mut arena: ResMut<Arena>,
+    mut objects: ResMut<LevelObjects>,
+) {
+    *config_changed |= config.is_changed();
+    if state.current() != &GameState::Prelude || !*config_changed {
+        return;
+    }
+    *config_changed = false;
+    let switched = current.as_ref() != Some(&config.world);
+    *current = Some(config.world.clone());
+
+    match &config.world {
+        WorldKind::Level(path) => {
+            if switched {
+                handle.0 = Some(asset_server.load(path.as_str()));
+            }
+            match handle.0.as_ref().and_then(|level| assets.get(level)) {
+                // Already loaded levels send no new event
+                Some(level) if switched => {
+                    *arena = level.arena.clone();
+                    objects.0 = level.objects.clone();
+                }
+                Some(_) => (),
+                // The built-in arena until the level loads, or for good if it fails to
+                None => {
+                    if switched {
+                        *arena = Arena::generate();
+                    }
+                    objects.0 = config
+                        .built_in_rocks
+                        .iter()
+                        .map(BuiltInRock::object)
+                        .collect();
+                }
+            }
+        }
+        WorldKind::Cave if switched => {
+            handle.0 = None;
+            *arena = Arena::endless(Cave::new(rng.seed()));
+            objects.0.clear();
+            info!("Generating an endless cave");
+        }
+        WorldKind::Cave => (),
+    }
+}
+
+/// Takes the level once it has loaded or changed, holding changes back until the next prelude.
+fn apply_level(
+    mut events: EventReader<AssetEvent<Level>>,
+    mut changed: Local<bool>,
+    state: Res<State<GameState>>,
+    assets: Res<Assets<Level>>,
+    handle: Res<LevelHandle>,
+    mut arena: ResMut<Arena>,
+    mut objects: ResMut<LevelObjects>,
+) {
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle: level } | AssetEvent::Modified { handle: level }
+                if handle.0.as_ref() == Some(level) =>
+            {
+                *changed = true;
+            }
+            _ => (),
+        }
+    }
+    if !*changed || state.current() != &GameState::Prelude {
+        return;
+    }
+    *changed = false;
+
+    if let Some(level) = handle.0.as_ref().and_then(|level| assets.get(level)) {
+        *arena = level.arena.clone();
+        objects.0 = level.objects.clone();
+        info!("Loaded a {}x{} level", arena.width, arena.height);
+    }
+}
+
+/// Places the level's objects when it is loaded and again every round.
+fn spawn_level_objects(
+    mut commands: Commands,
+    mut events: EventReader<RestartGame>,
+    objects: Res<LevelObjects>,
+    config: Res<GameConfig>,
+    spawned: Query<(Entity, Option<&FuelPickup>), With<FromLevel>>,
+) {
+    let restarted = events.iter().count() > 0;
+    if !restarted && !objects.is_changed() {
+        return;
+    }
+
+    for (entity, pickup) in &spawned {
+        // Restarting already clears every fuel pickup
+        if !(restarted && pickup.is_some()) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    for object in &objects.0 {
+        let entity = match *object {
+            LevelObject::Light {
+                position,
+                radius,
+                color,
+                intensity,
+            } => commands
+                .spawn_bundle(SpatialBundle {
+                    transform: Transform::from_translation(position.extend(0.)),
+                    ..default()
+                })
+                .insert(Name::new("Light"))
+                .insert(PointLight2d {
+                    radius,
+                    color,
+                    intensity,
+                })
+                .id(),
+            LevelObject::Fuel { position, amount } => spawn_fuel_pickup(
+                &mut commands,
+                position,
+                amount.unwrap_or(config.fuel_pickup_amount),
+            ),
+            LevelObject::Rock { position, size } => spawn_rock(&mut commands, position, size),
+        };
+        commands.entity(entity).insert(FromLevel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x2 map with 16 pixel tiles, so map pixels are half a world unit.
+    const MAP: &str = r##"{
+        "width": 2,
+        "height": 2,
+        "tilewidth": 16,
+        "tilesets": [{ "firstgid": 1 }],
+        "layers": [
+            { "type": "tilelayer", "name": "floor", "data": [1, 2, 3, 4] },
+            { "type": "tilelayer", "name": "walls", "data": [0, 2147483652, 0, 0] },
+            { "type": "objectgroup", "objects": [
+                { "class": "player_spawn", "x": 16, "y": 8 },
+                { "class": "light", "x": 0, "y": 16, "properties": [
+                    { "name": "color", "type": "color", "value": "#80ff0000" }
+                ] },
+                { "class": "rock", "x": 0, "y": 0, "width": 16, "height": 8 }
+            ] }
+        ]
+    }"##;
+
+    fn level(json: &str) -> Result<Level, bevy::asset::Error> {
+        serde_json::from_str::<TiledMap>(json).unwrap().into_level()
+    }
+
+    #[test]
+    fn rows_run_top_to_bottom() {
+        let arena = level(MAP).unwrap().arena;
+
+        assert_eq!(arena.floor_texture(0, 1), 0);
+        assert_eq!(arena.floor_texture(1, 1), 1);
+        assert_eq!(arena.floor_texture(0, 0), 2);
+        assert_eq!(arena.floor_texture(1, 0), 3);
+    }
+
+    #[test]
+    fn flip_flags_are_masked_out() {
+        let arena = level(MAP).unwrap().arena;
+
+        assert_eq!(arena.tile(1, 1), Some(Tile::Wall));
+        assert_eq!(arena.tile(0, 0), Some(Tile::Floor));
+    }
+
+    #[test]
+    fn objects_are_placed_from_the_bottom_left() {
+        let level = level(MAP).unwrap();
+
+        assert_eq!(level.arena.player_start, Vec2::new(0., 4.));
+        assert_eq!(
+            level.objects,
+            vec![
+                LevelObject::Light {
+                    position: Vec2::new(-8., 0.),
+                    radius: 24.,
+                    color: Color::rgba(1., 0., 0., 128. / 255.),
+                    intensity: 0.8,
+                },
+                LevelObject::Rock {
+                    position: Vec2::new(-4., 6.),
+                    size: Vec2::new(8., 4.),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tile_layers_must_cover_the_map() {
+        let map = MAP.replace("[1, 2, 3, 4]", "[1, 2, 3]");
+
+        assert!(level(&map).is_err());
+    }
+
+    #[test]
+    fn parses_tiled_colors() {
+        assert_eq!(
+            parse_tiled_color("#ff8000"),
+            Some(Color::rgb(1., 128. / 255., 0.))
+        );
+        assert_eq!(
+            parse_tiled_color("#40ff8000"),
+            Some(Color::rgba(1., 128. / 255., 0., 64. / 255.))
+        );
+        assert_eq!(parse_tiled_color("ff8000"), None);
+        assert_eq!(parse_tiled_color("#f80"), None);
+        assert_eq!(parse_tiled_color("#gg8000"), None);
+    }
+}
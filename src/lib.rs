@@ -0,0 +1,28 @@
+#![allow(clippy::type_complexity)]
+
+pub mod animation;
+pub mod arena;
+pub mod audio;
+pub mod camera;
+pub mod cave;
+pub mod character;
+pub mod config;
+pub mod events;
+pub mod fuel;
+pub mod game;
+pub mod highscore;
+pub mod input;
+pub mod level;
+pub mod lighting;
+pub mod music;
+pub mod obstacle;
+pub mod pause;
+pub mod physics;
+pub mod rendering;
+pub mod replay;
+pub mod rng;
+pub mod sfx;
+pub mod storage;
+pub mod text;
+
+pub use game::{ContourHeadlessPlugins, ContourPlugins, GamePlugin, GameState};
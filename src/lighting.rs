@@ -1,11 +1,39 @@
-use bevy::prelude::*;
+use std::f32::consts::TAU;
 
-use crate::character::{
-    player::{Lantern, PlayerPosition},
-    MoveDirection,
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::render_resource::{AsBindGroup, ShaderRef, ShaderType},
+    sprite::{Material2d, Material2dPlugin, Mesh2dHandle},
+    time::FixedTimesteps,
 };
+use bevy_rapier2d::prelude::{QueryFilter, RapierContext};
 
-#[derive()]
+use crate::{
+    character::{
+        player::{Lantern, LanternFuel, LightDirection, PlayerPosition},
+        Position,
+    },
+    config::GameConfig,
+    events::GlobalLightExtinguished,
+    game::{step_progress, FixedUpdateStage, GameState, RestartGame, FIXED_TIMESTEP},
+    input::{Action, KeyBindings},
+};
+
+/// Lights beyond this many are left out of the darkness mask, farthest from the mask first.
+///
+/// Must match the array length in `assets/shaders/darkness.wgsl`.
+pub const MAX_LIGHTS: usize = 16;
+
+/// Rays cast around each light to find where occluders cut it off.
+///
+/// Must match `assets/shaders/darkness.wgsl`, which receives them packed four to a `vec4`.
+pub const SHADOW_RAYS: usize = 128;
+
+/// How far past an occluder's edge the light fades out, so walls catch a little light.
+const SHADOW_SOFTNESS: f32 = 2.;
+
+#[derive(PartialEq, Eq)]
 pub enum LightingMode {
     Dark,
     Lantern,
@@ -19,38 +47,234 @@ pub struct Lighting(pub LightingMode);
 #[derive(Component)]
 pub struct GlobalLight(pub bool);
 
+/// A light shining in every direction, fading out towards `radius`.
+#[derive(Component, Clone)]
+pub struct PointLight2d {
+    pub radius: f32,
+    pub color: Color,
+    pub intensity: f32,
+}
+
+/// A light shining along `direction`, `angle` radians wide, fading out towards `radius`.
+#[derive(Component, Clone)]
+pub struct ConeLight2d {
+    pub radius: f32,
+    pub angle: f32,
+    pub direction: Vec2,
+    pub color: Color,
+    pub intensity: f32,
+}
+
+/// The lights carried by the player, lit together with the `Lantern`.
+#[derive(Component)]
+pub struct LanternLight;
+
+/// Gameplay steps played this round, so the lantern flickers the same way on every replay.
+#[derive(Default)]
+struct LanternClock(u64);
+
+/// Blocks light with the entity's rapier `Collider`, casting a shadow behind it.
+#[derive(Component)]
+pub struct Occluder;
+
+/// Every light source in the world, gathered at the start of every gameplay step so gameplay can
+/// ask how lit a point is.
+///
+/// Overlapping lights do not add up: a point is as lit as the brightest light reaching it.
+#[derive(Default)]
+pub struct LightField {
+    global: bool,
+    sources: Vec<LightSource>,
+}
+
+#[derive(Clone)]
+struct LightSource {
+    position: Vec2,
+    /// Where the light was on the previous gameplay step, so the mask can be interpolated.
+    previous: Vec2,
+    direction: Vec2,
+    radius: f32,
+    /// Cosine of half the cone angle, -1 for point lights.
+    cos_half_angle: f32,
+    color: Color,
+    intensity: f32,
+    /// Distance the light travels before hitting an occluder, per ray, counter-clockwise from +x.
+    reach: [f32; SHADOW_RAYS],
+}
+
+impl LightField {
+    /// How much light reaches `point`, from 0 in the dark to 1 under the global light.
+    pub fn light_at(&self, point: Vec2) -> f32 {
+        if self.global {
+            return 1.;
+        }
+
+        self.sources
+            .iter()
+            .map(|source| source.light_at(point))
+            .fold(0., f32::max)
+            .min(1.)
+    }
+
+    pub fn is_lit(&self, point: Vec2) -> bool {
+        self.light_at(point) > 0.
+    }
+
+    /// A single unobstructed point light.
+    #[cfg(test)]
+    pub(crate) fn point_light(position: Vec2, radius: f32) -> Self {
+        LightField {
+            global: false,
+            sources: vec![LightSource {
+                position,
+                previous: position,
+                direction: Vec2::X,
+                radius,
+                cos_half_angle: -1.,
+                color: Color::WHITE,
+                intensity: 1.,
+                reach: [radius; SHADOW_RAYS],
+            }],
+        }
+    }
+
+    /// Position of the closest burning light within `max_distance` of `point`.
+    pub fn nearest_source(&self, point: Vec2, max_distance: f32) -> Option<Vec2> {
+        self.sources
+            .iter()
+            .map(|source| source.position)
+            .filter(|position| position.distance(point) <= max_distance)
+            .min_by(|a, b| {
+                a.distance_squared(point)
+                    .total_cmp(&b.distance_squared(point))
+            })
+    }
+}
+
+impl LightSource {
+    /// Same falloff as `assets/shaders/darkness.wgsl`, so gameplay agrees with what is drawn.
+    fn light_at(&self, point: Vec2) -> f32 {
+        let offset = point - self.position;
+        let distance = offset.length();
+
+        let mut amount = (1. - distance / self.radius).clamp(0., 1.);
+        if self.cos_half_angle > -1. && distance > 0. {
+            let cos_angle = (offset / distance).dot(self.direction);
+            let edge = self.cos_half_angle + (1. - self.cos_half_angle) * 0.2;
+            amount *= smoothstep(self.cos_half_angle, edge, cos_angle);
+        }
+        amount *= 1. - smoothstep(0., SHADOW_SOFTNESS, distance - self.reach_towards(offset));
+        amount * self.intensity
+    }
+
+    /// Interpolates between the two rays around `offset`.
+    fn reach_towards(&self, offset: Vec2) -> f32 {
+        let ray = offset.y.atan2(offset.x).rem_euclid(TAU) / TAU * SHADOW_RAYS as f32;
+        let first = ray.floor() as usize % SHADOW_RAYS;
+        let second = (first + 1) % SHADOW_RAYS;
+        let t = ray.fract();
+        self.reach[first] * (1. - t) + self.reach[second] * t
+    }
+
+    fn cast_shadows(&mut self, rapier: &RapierContext, filter: QueryFilter) {
+        for (ray, reach) in self.reach.iter_mut().enumerate() {
+            let angle = ray as f32 / SHADOW_RAYS as f32 * TAU;
+            let direction = Vec2::new(angle.cos(), angle.sin());
+            *reach = rapier
+                .cast_ray(self.position, direction, self.radius, true, filter)
+                .map_or(self.radius, |(_, distance)| distance);
+        }
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
 pub struct LightingPlugin;
 
 impl Plugin for LightingPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(setup)
-            .add_system(follow_player)
+        app.init_resource::<LightField>()
+            .init_resource::<LanternClock>()
+            .add_startup_system(setup)
+            .add_system_to_stage(CoreStage::Last, follow_player)
             .add_system(kill_global_light)
-            .add_system(global_light_trigger)
-            .add_system(lantern_light_trigger)
-            .add_system(update_lighting_sprite);
+            .add_system(lighting_mode_trigger)
+            .add_system_set_to_stage(
+                FixedUpdateStage,
+                SystemSet::on_update(GameState::InGame).with_system(tick_lantern_clock),
+            )
+            .add_system_to_stage(FixedUpdateStage, lantern_light.after(tick_lantern_clock))
+            // Before any gameplay asks for light in the step.
+            .add_system_to_stage(
+                FixedUpdateStage,
+                update_light_field.exclusive_system().at_start(),
+            )
+            .add_system(reset_lighting);
     }
 }
 
-pub fn setup(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-) {
-    let mut transform = Transform::from_xyz(0., 0., 998.);
-    transform.scale = Vec3::new(2., 2., 2.);
-    let texture_handle = asset_server.load("darkness.png");
-
-    let texture_atlas_handle = texture_atlases.add(TextureAtlas::from_grid(
-        texture_handle,
-        Vec2 { x: 640., y: 320. },
-        2,
-        1,
-    ));
+/// Draws the darkness mask over the world, cut open by every `PointLight2d` and `ConeLight2d`.
+pub struct LightingMaskPlugin;
+
+impl Plugin for LightingMaskPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(Material2dPlugin::<DarknessMaterial>::default())
+            .add_system_to_stage(CoreStage::PostUpdate, insert_mask)
+            .add_system_to_stage(CoreStage::PostUpdate, update_mask);
+    }
+}
+
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "3b8f5d2e-71c4-4a09-b6e2-9c5d1f0a8e47"]
+pub struct DarknessMaterial {
+    #[uniform(0)]
+    darkness: DarknessUniform,
+}
+
+impl Material2d for DarknessMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/darkness.wgsl".into()
+    }
+}
+
+#[derive(Clone, ShaderType)]
+struct DarknessUniform {
+    color: Vec4,
+    count: u32,
+    lights: [GpuLight; MAX_LIGHTS],
+    /// `LightSource::reach` of every light, four rays to a `vec4` to fit uniform array alignment.
+    reach: [Vec4; MAX_LIGHTS * SHADOW_RAYS / 4],
+}
+
+impl Default for DarknessUniform {
+    fn default() -> Self {
+        DarknessUniform {
+            color: Vec4::ZERO,
+            count: 0,
+            lights: default(),
+            reach: [Vec4::ZERO; MAX_LIGHTS * SHADOW_RAYS / 4],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, ShaderType)]
+struct GpuLight {
+    color: Vec4,
+    position: Vec2,
+    direction: Vec2,
+    radius: f32,
+    /// Cosine of half the cone angle, -1 for point lights.
+    cos_half_angle: f32,
+    intensity: f32,
+}
+
+pub fn setup(mut commands: Commands) {
     commands
-        .spawn_bundle(SpriteSheetBundle {
-            texture_atlas: texture_atlas_handle,
-            transform,
+        .spawn_bundle(SpatialBundle {
+            transform: Transform::from_xyz(0., 0., 998.),
             ..default()
         })
         .insert(Name::new("Lighting"))
@@ -60,77 +284,246 @@ pub fn setup(
 }
 
 fn follow_player(
-    players: Query<(&PlayerPosition, &MoveDirection)>,
+    players: Query<&PlayerPosition>,
     mut follows: Query<&mut Transform, With<Follow>>,
 ) {
-    for (player_position, light_direction) in &players {
+    for player_position in &players {
         for mut follow_transform in &mut follows {
-            follow_transform.translation.x = player_position.x + light_direction.0.x * 10.;
-            follow_transform.translation.y = player_position.y + 8.0 + light_direction.0.y * 10.;
+            follow_transform.translation.x = player_position.x;
+            follow_transform.translation.y = player_position.y;
         }
     }
 }
 
-fn global_light_trigger(
-    mut query: Query<&mut Lighting, Changed<GlobalLight>>,
+fn lighting_mode_trigger(
+    mut query: Query<(&mut Lighting, &GlobalLight)>,
     lantern_query: Query<&Lantern>,
 ) {
-    for mut lighting in &mut query {
+    for (mut lighting, global_light) in &mut query {
         for lantern in &lantern_query {
-            match lighting.0 {
-                LightingMode::Light => {
-                    lighting.0 = if lantern.0 {
-                        LightingMode::Lantern
-                    } else {
-                        LightingMode::Dark
-                    }
-                }
-                LightingMode::Dark => lighting.0 = LightingMode::Light,
-                LightingMode::Lantern => lighting.0 = LightingMode::Light,
+            let mode = match (global_light.0, lantern.0) {
+                (true, _) => LightingMode::Light,
+                (false, true) => LightingMode::Lantern,
+                (false, false) => LightingMode::Dark,
+            };
+            if lighting.0 != mode {
+                lighting.0 = mode;
             }
         }
     }
 }
 
-fn lantern_light_trigger(
-    mut query: Query<&mut Lighting>,
-    lantern_query: Query<&Lantern, Changed<Lantern>>,
+fn tick_lantern_clock(mut clock: ResMut<LanternClock>) {
+    clock.0 += 1;
+}
+
+fn lantern_light(
+    clock: Res<LanternClock>,
+    config: Res<GameConfig>,
+    players: Query<(&Lantern, &LanternFuel, &LightDirection)>,
+    mut lights: Query<(&mut ConeLight2d, &mut PointLight2d), With<LanternLight>>,
 ) {
-    for mut lighting in &mut query {
-        for _ in &lantern_query {
-            match lighting.0 {
-                LightingMode::Dark => lighting.0 = LightingMode::Lantern,
-                LightingMode::Lantern => lighting.0 = LightingMode::Dark,
-                LightingMode::Light => (),
+    for (lantern, fuel, light_direction) in &players {
+        for (mut cone, mut glow) in &mut lights {
+            let intensity = if lantern.0 {
+                lantern_intensity(
+                    fuel.fraction(),
+                    config.lantern_low_fuel,
+                    clock.0 as f64 * FIXED_TIMESTEP,
+                )
+            } else {
+                0.
             };
+            cone.intensity = intensity;
+            glow.intensity = intensity;
+
+            // Keep pointing the last way the player faced when standing still.
+            if light_direction.0 != Vec2::ZERO {
+                cone.direction = light_direction.0.normalize();
+            }
         }
     }
 }
 
-fn update_lighting_sprite(
-    mut query: Query<(&mut TextureAtlasSprite, &mut Lighting, &mut Visibility), Changed<Lighting>>,
+/// Full brightness until the fuel runs low, then dimmer and flickering harder the emptier it gets.
+fn lantern_intensity(fuel: f32, low_fuel: f32, seconds: f64) -> f32 {
+    if fuel >= low_fuel || low_fuel <= 0. {
+        return 1.;
+    }
+
+    let low = 1. - fuel / low_fuel;
+    // Two out-of-phase waves are irregular enough to read as a guttering flame.
+    let flicker = ((seconds * 23.).sin() * (seconds * 7.3).sin()).abs() as f32;
+    (1. - 0.4 * low) * (1. - 0.5 * low * flicker)
+}
+
+/// Where a light is on the current and the previous gameplay step, rather than where the frame
+/// interpolated it to.
+///
+/// Lights carried by a character are offset from its `Position`; other lights stay where they were put.
+fn light_positions(
+    transform: &Transform,
+    parent: Option<&Parent>,
+    positions: &Query<&Position>,
+) -> (Vec2, Vec2) {
+    let offset = transform.translation.truncate();
+    match parent.and_then(|parent| positions.get(parent.get()).ok()) {
+        Some(position) => (position.current + offset, position.previous + offset),
+        None => (offset, offset),
+    }
+}
+
+fn update_light_field(
+    mut field: ResMut<LightField>,
+    global_light: Query<&GlobalLight>,
+    point_lights: Query<(&Transform, Option<&Parent>, &PointLight2d)>,
+    cone_lights: Query<(&Transform, Option<&Parent>, &ConeLight2d)>,
+    positions: Query<&Position>,
+    occluders: Query<(), With<Occluder>>,
+    rapier: Res<RapierContext>,
 ) {
-    for (mut sprite, lighting, mut visibility) in &mut query {
-        match lighting.0 {
-            LightingMode::Dark => {
-                sprite.index = 1;
-                visibility.is_visible = true;
-            }
-            LightingMode::Lantern => {
-                sprite.index = 0;
-                visibility.is_visible = true;
-            }
-            LightingMode::Light => {
-                visibility.is_visible = false;
+    field.global = global_light.iter().any(|light| light.0);
+
+    field.sources.clear();
+    let points = point_lights.iter().map(|(transform, parent, light)| {
+        let (position, previous) = light_positions(transform, parent, &positions);
+        LightSource {
+            position,
+            previous,
+            direction: Vec2::X,
+            radius: light.radius,
+            cos_half_angle: -1.,
+            color: light.color,
+            intensity: light.intensity,
+            reach: [light.radius; SHADOW_RAYS],
+        }
+    });
+    let cones = cone_lights.iter().map(|(transform, parent, light)| {
+        let (position, previous) = light_positions(transform, parent, &positions);
+        LightSource {
+            position,
+            previous,
+            direction: light.direction.normalize_or_zero(),
+            radius: light.radius,
+            cos_half_angle: (light.angle / 2.).cos(),
+            color: light.color,
+            intensity: light.intensity,
+            reach: [light.radius; SHADOW_RAYS],
+        }
+    });
+    field.sources.extend(
+        points
+            .chain(cones)
+            .filter(|source| source.intensity > 0. && source.radius > 0.),
+    );
+
+    if occluders.is_empty() {
+        return;
+    }
+    let is_occluder = |entity| occluders.contains(entity);
+    let filter = QueryFilter::new().predicate(&is_occluder);
+    for source in &mut field.sources {
+        source.cast_shadows(&rapier, filter);
+    }
+}
+
+fn reset_lighting(
+    mut events: EventReader<RestartGame>,
+    mut query: Query<&mut GlobalLight>,
+    mut clock: ResMut<LanternClock>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    clock.0 = 0;
+    for mut global_light in &mut query {
+        global_light.0 = true;
+    }
+}
+
+fn insert_mask(
+    mut commands: Commands,
+    query: Query<Entity, Added<Lighting>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<DarknessMaterial>>,
+) {
+    for entity in &query {
+        commands
+            .entity(entity)
+            .insert(Mesh2dHandle(
+                meshes.add(shape::Quad::new(Vec2::splat(1280.)).into()),
+            ))
+            .insert(materials.add(DarknessMaterial {
+                darkness: DarknessUniform {
+                    color: Vec4::from_array(Color::BLACK.as_linear_rgba_f32()),
+                    ..default()
+                },
+            }));
+    }
+}
+
+fn update_mask(
+    mut masks: Query<(
+        &Lighting,
+        &GlobalTransform,
+        &Handle<DarknessMaterial>,
+        &mut Visibility,
+    )>,
+    field: Res<LightField>,
+    timesteps: Res<FixedTimesteps>,
+    mut materials: ResMut<Assets<DarknessMaterial>>,
+) {
+    // Drawn where the sprites are, between the last two gameplay steps
+    let progress = step_progress(&timesteps);
+    for (lighting, mask_transform, handle, mut visibility) in &mut masks {
+        let is_visible = lighting.0 != LightingMode::Light;
+        if visibility.is_visible != is_visible {
+            visibility.is_visible = is_visible;
+        }
+        if !is_visible {
+            continue;
+        }
+
+        let center = mask_transform.translation().truncate();
+        let mut sources: Vec<&LightSource> = field.sources.iter().collect();
+        sources.sort_by(|a, b| {
+            a.position
+                .distance_squared(center)
+                .total_cmp(&b.position.distance_squared(center))
+        });
+        sources.truncate(MAX_LIGHTS);
+
+        if let Some(material) = materials.get_mut(handle) {
+            let darkness = &mut material.darkness;
+            darkness.count = sources.len() as u32;
+            for (light, source) in sources.iter().enumerate() {
+                darkness.lights[light] = GpuLight {
+                    color: Vec4::from_array(source.color.as_linear_rgba_f32()),
+                    position: source.previous.lerp(source.position, progress),
+                    direction: source.direction,
+                    radius: source.radius,
+                    cos_half_angle: source.cos_half_angle,
+                    intensity: source.intensity,
+                };
+                for (rays, reach) in source.reach.chunks_exact(4).enumerate() {
+                    darkness.reach[light * SHADOW_RAYS / 4 + rays] = Vec4::from_slice(reach);
+                }
             }
         }
     }
 }
 
-fn kill_global_light(input: Res<Input<KeyCode>>, mut query: Query<&mut GlobalLight>) {
+fn kill_global_light(
+    input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut query: Query<&mut GlobalLight>,
+    mut extinguished: EventWriter<GlobalLightExtinguished>,
+) {
     for mut global_light in &mut query {
-        if input.just_pressed(KeyCode::G) && global_light.0 {
+        if bindings.just_pressed(&input, Action::KillGlobalLight) && global_light.0 {
             global_light.0 = false;
+            extinguished.send(GlobalLightExtinguished);
         }
     }
 }
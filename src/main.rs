@@ -1,33 +1,49 @@
-#![allow(clippy::type_complexity)]
-
-mod animation;
-mod camera;
-mod character;
-mod game;
-mod lighting;
-mod rendering;
-mod text;
-
 use bevy::prelude::*;
 use bevy_egui::EguiPlugin;
 use bevy_kira_audio::AudioPlugin;
-use bevy_rapier2d::prelude::{NoUserData, RapierPhysicsPlugin};
-use game::{play_audio_system, ContourPlugins, GameState};
-use rendering::RenderingPlugin;
+use contour::{
+    rendering::RenderingPlugin,
+    replay::{InputRecorder, InputReplay, Replay},
+    rng::GameRng,
+    ContourPlugins,
+};
 
 fn main() {
-    App::new()
+    let mut app = App::new();
+
+    if let Some(seed) = arg_value("--seed").or_else(|| std::env::var("CONTOUR_SEED").ok()) {
+        app.insert_resource(GameRng::new(
+            seed.parse()
+                .expect("The seed must be an unsigned 64-bit integer."),
+        ));
+    }
+    if let Some(path) = arg_value("--record") {
+        app.insert_resource(InputRecorder::new(path));
+    }
+    if let Some(path) = arg_value("--replay") {
+        let replay = Replay::load(&path).expect("The replay file could not be read.");
+        app.insert_resource(InputReplay::new(replay));
+    }
+
+    // Hot-reload the gameplay config while playing
+    #[cfg(not(target_arch = "wasm32"))]
+    app.insert_resource(bevy::asset::AssetServerSettings {
+        watch_for_changes: true,
+        ..default()
+    });
+
+    app
         // Setup stuff
         .add_plugin(RenderingPlugin)
         .add_plugins(DefaultPlugins)
         .add_plugin(AudioPlugin)
         .add_plugin(EguiPlugin)
-        // Physics
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0))
         // Game specific
-        .add_state(GameState::Prelude)
-        .add_state_to_stage(CoreStage::PostUpdate, GameState::Prelude)
         .add_plugins(ContourPlugins)
-        .add_startup_system(play_audio_system)
         .run();
 }
+
+/// Reads the value following `name` on the command line, e.g. `--seed 42`.
+fn arg_value(name: &str) -> Option<String> {
+    std::env::args().skip_while(|arg| arg != name).nth(1)
+}
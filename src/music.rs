@@ -0,0 +1,234 @@
+//! The adaptive soundtrack: stems looping in sync on the music channel, mixed by how much danger
+//! the player is in, and a sting on game over.
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_kira_audio::prelude::*;
+
+use crate::{
+    audio::{AudioLibrary, AudioSettings, MusicChannel, FADE},
+    character::{
+        enemy::Enemy,
+        merge::Merge,
+        player::{Lantern, PlayerPosition},
+        Position,
+    },
+    game::GameState,
+    lighting::GlobalLight,
+};
+
+/// Enemies further away than this do not add to the danger.
+const DANGER_RADIUS: f32 = 60.;
+/// Danger at which the pulse plays at full volume. Every nearby enemy adds its merge level plus
+/// one, scaled down with distance.
+const FULL_DANGER: f32 = 6.;
+/// How fast the mix follows the danger, in fractions of the difference per second.
+const MIX_RATE: f32 = 1.5;
+/// Smallest volume change sent to a playing stem, and how long it takes to apply.
+const VOLUME_STEP: f64 = 0.01;
+const VOLUME_TWEEN: Duration = Duration::from_millis(100);
+/// Seconds the stems take to fade in at the start and out on game over.
+const START_FADE: f32 = 1.5;
+const STOP_FADE: f32 = 0.3;
+
+/// One layer of the soundtrack. All stems have the same length and tempo.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stem {
+    /// Always playing, quieter in the dark.
+    Calm,
+    /// Grows with the danger.
+    Pulse,
+    /// Takes over while the player walks without any light.
+    Dark,
+}
+
+impl Stem {
+    const ALL: [Stem; 3] = [Stem::Calm, Stem::Pulse, Stem::Dark];
+
+    fn path(self) -> &'static str {
+        match self {
+            Stem::Calm => "bgm.wav",
+            Stem::Pulse => "music/pulse.wav",
+            Stem::Dark => "music/dark.wav",
+        }
+    }
+
+    /// Volume of the stem in `mix`, with the music at `music_volume`.
+    fn volume(self, mix: &MusicMix, music_volume: f64) -> f64 {
+        let volume = match self {
+            Stem::Calm => 1. - 0.6 * mix.dark,
+            Stem::Pulse => mix.danger,
+            Stem::Dark => mix.dark,
+        };
+        volume as f64 * music_volume
+    }
+}
+
+/// How the stems are mixed right now, every layer from 0 to 1.
+#[derive(Default)]
+pub struct MusicMix {
+    pub danger: f32,
+    pub dark: f32,
+}
+
+struct Soundtrack {
+    stems: Vec<(Stem, Handle<AudioSource>)>,
+    sting: Handle<AudioSource>,
+    /// Whether the stems should be playing, i.e. outside of game over.
+    wanted: bool,
+    /// Playing stems with the volume last sent to them.
+    playing: Vec<(Stem, Handle<AudioInstance>, f64)>,
+}
+
+pub struct AdaptiveMusicPlugin;
+
+impl Plugin for AdaptiveMusicPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MusicMix>()
+            .add_startup_system(setup)
+            .add_system(follow_danger)
+            .add_system(play_sting_on_game_over)
+            .add_system(play_stems.after(play_sting_on_game_over))
+            .add_system(mix_stems.after(follow_danger).after(play_stems));
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut library: ResMut<AudioLibrary>,
+) {
+    commands.insert_resource(Soundtrack {
+        stems: Stem::ALL
+            .into_iter()
+            .map(|stem| (stem, library.load(&asset_server, stem.path())))
+            .collect(),
+        sting: library.load(&asset_server, "music/sting.wav"),
+        wanted: true,
+        playing: Vec::new(),
+    });
+}
+
+/// Eases the mix towards the danger the player is in right now.
+fn follow_danger(
+    time: Res<Time>,
+    player: Query<(&Position, &Lantern), With<PlayerPosition>>,
+    enemies: Query<(&Position, &Merge), With<Enemy>>,
+    global_light: Query<&GlobalLight>,
+    mut mix: ResMut<MusicMix>,
+) {
+    let (danger, dark) = match player.get_single() {
+        Ok((player, lantern)) => {
+            let danger: f32 = enemies
+                .iter()
+                .map(|(position, merge)| {
+                    let closeness =
+                        1. - (position.current - player.current).length() / DANGER_RADIUS;
+                    (merge.level + 1) as f32 * closeness.max(0.)
+                })
+                .sum();
+            let lit = lantern.0 || global_light.iter().any(|light| light.0);
+            ((danger / FULL_DANGER).min(1.), if lit { 0. } else { 1. })
+        }
+        Err(_) => (0., 0.),
+    };
+
+    let step = (MIX_RATE * time.delta_seconds()).min(1.);
+    mix.danger += (danger - mix.danger) * step;
+    mix.dark += (dark - mix.dark) * step;
+}
+
+fn play_sting_on_game_over(
+    state: Res<State<GameState>>,
+    settings: Res<AudioSettings>,
+    mut soundtrack: ResMut<Soundtrack>,
+    channel: Res<AudioChannel<MusicChannel>>,
+    asset_server: Res<AssetServer>,
+) {
+    if !state.is_changed() {
+        return;
+    }
+
+    let game_over = state.current() == &GameState::GameOver;
+    if game_over && soundtrack.wanted && AudioLibrary::is_ready(&asset_server, &soundtrack.sting) {
+        channel
+            .play(soundtrack.sting.clone())
+            .with_volume(settings.music_volume(state.current()));
+    }
+    soundtrack.wanted = !game_over;
+}
+
+/// Starts every stem at once when they are all loaded, so they stay in sync, and stops them.
+fn play_stems(
+    mut soundtrack: ResMut<Soundtrack>,
+    mix: Res<MusicMix>,
+    state: Res<State<GameState>>,
+    settings: Res<AudioSettings>,
+    channel: Res<AudioChannel<MusicChannel>>,
+    mut instances: ResMut<Assets<AudioInstance>>,
+    asset_server: Res<AssetServer>,
+    library: Res<AudioLibrary>,
+) {
+    let soundtrack = &mut *soundtrack;
+    if !soundtrack.wanted {
+        for (_, instance, _) in soundtrack.playing.drain(..) {
+            if let Some(instance) = instances.get_mut(&instance) {
+                instance.stop(AudioTween::linear(Duration::from_secs_f32(STOP_FADE)));
+            }
+        }
+        return;
+    }
+    if !soundtrack.playing.is_empty()
+        || soundtrack
+            .stems
+            .iter()
+            .any(|(_, source)| library.is_loading(source))
+    {
+        return;
+    }
+
+    // Missing stems are left out of the mix
+    let music_volume = settings.music_volume(state.current());
+    soundtrack.playing = soundtrack
+        .stems
+        .iter()
+        .filter(|(_, source)| AudioLibrary::is_ready(&asset_server, source))
+        .map(|(stem, source)| {
+            let volume = stem.volume(&mix, music_volume);
+            let instance = channel
+                .play(source.clone())
+                .looped()
+                .with_volume(volume)
+                .fade_in(AudioTween::linear(Duration::from_secs_f32(START_FADE)))
+                .handle();
+            (*stem, instance, volume)
+        })
+        .collect();
+}
+
+fn mix_stems(
+    mut soundtrack: ResMut<Soundtrack>,
+    mix: Res<MusicMix>,
+    state: Res<State<GameState>>,
+    settings: Res<AudioSettings>,
+    mut instances: ResMut<Assets<AudioInstance>>,
+) {
+    let music_volume = settings.music_volume(state.current());
+    // Pausing and resuming fade like the other channels
+    let tween = if state.is_changed() {
+        Duration::from_secs_f32(FADE)
+    } else {
+        VOLUME_TWEEN
+    };
+    for (stem, instance, sent) in &mut soundtrack.playing {
+        let volume = stem.volume(&mix, music_volume);
+        if (volume - *sent).abs() < VOLUME_STEP {
+            continue;
+        }
+        if let Some(instance) = instances.get_mut(instance) {
+            instance.set_volume(volume, AudioTween::linear(tween));
+            *sent = volume;
+        }
+    }
+}
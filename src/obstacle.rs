@@ -0,0 +1,47 @@
+use bevy::{prelude::*, render::texture::DEFAULT_IMAGE_HANDLE};
+use bevy_rapier2d::prelude::{Collider, RigidBody, SolverGroups};
+
+use crate::{lighting::Occluder, physics::WORLD_GROUP, rendering};
+
+/// A rock, placed by the level. Enemies can lurk in its shadow.
+#[derive(Component)]
+pub struct Obstacle {
+    pub size: Vec2,
+}
+
+pub struct ObstacleSpritePlugin;
+
+impl Plugin for ObstacleSpritePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_to_stage(CoreStage::PostUpdate, insert_sprite);
+    }
+}
+
+pub fn spawn_rock(commands: &mut Commands, position: Vec2, size: Vec2) -> Entity {
+    commands
+        .spawn_bundle(SpatialBundle {
+            transform: Transform::from_translation(position.extend(0.)),
+            ..default()
+        })
+        .insert(Name::new("Rock"))
+        .insert(Obstacle { size })
+        .insert(RigidBody::Fixed)
+        .insert(Collider::cuboid(size.x / 2., size.y / 2.))
+        .insert(SolverGroups::new(WORLD_GROUP, u32::MAX))
+        .insert(Occluder)
+        .insert(rendering::OrderedZ)
+        .id()
+}
+
+fn insert_sprite(mut commands: Commands, query: Query<(Entity, &Obstacle), Added<Obstacle>>) {
+    for (entity, obstacle) in &query {
+        commands
+            .entity(entity)
+            .insert(Sprite {
+                color: Color::rgb(0.35, 0.33, 0.3),
+                custom_size: Some(obstacle.size),
+                ..default()
+            })
+            .insert(DEFAULT_IMAGE_HANDLE.typed::<Image>());
+    }
+}
@@ -0,0 +1,165 @@
+use bevy::{app::AppExit, prelude::*};
+use bevy_egui::{egui, EguiContext};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audio::AudioSettings,
+    game::{GameState, RestartGame},
+    input::{Action, ControlsMenu, KeyBindings},
+};
+
+pub struct PausePlugin;
+
+impl Plugin for PausePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<MenuAction>()
+            .add_system_set(SystemSet::on_update(GameState::InGame).with_system(pause_on_input))
+            .add_system_set(SystemSet::on_update(GameState::Paused).with_system(menu_input))
+            .add_system(apply_menu_buttons.label(ApplyMenuButtons));
+    }
+}
+
+pub struct PauseMenuPlugin;
+
+impl Plugin for PauseMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(GameState::Paused)
+                .with_system(show_pause_menu.before(ApplyMenuButtons)),
+        )
+        .add_system(show_cursor_in_menus);
+    }
+}
+
+/// A menu button, sent as an event rather than acted on directly so replays can record it.
+///
+/// Menu keys are not sent, replays already have the keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MenuAction {
+    Resume,
+    Restart,
+    Quit,
+}
+
+/// Systems sending `MenuAction` run before this, so a click is acted on in the frame it is recorded.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub struct ApplyMenuButtons;
+
+// Keys that change the state are reset once handled, as the state change reruns the `on_update`
+// sets in the same frame, where the next state's systems would see the same press.
+
+fn pause_on_input(mut input: ResMut<Input<KeyCode>>, mut state: ResMut<State<GameState>>) {
+    if input.just_pressed(KeyCode::Escape) {
+        input.reset(KeyCode::Escape);
+        state
+            .push(GameState::Paused)
+            .expect("State must be added to the game at this point.");
+    }
+}
+
+fn menu_input(
+    mut input: ResMut<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut state: ResMut<State<GameState>>,
+    mut restart: EventWriter<RestartGame>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let restart_key = bindings.key(Action::Restart);
+    let (action, key) = if input.just_pressed(KeyCode::Escape) {
+        (MenuAction::Resume, KeyCode::Escape)
+    } else if input.just_pressed(restart_key) {
+        (MenuAction::Restart, restart_key)
+    } else if input.just_pressed(KeyCode::Q) {
+        (MenuAction::Quit, KeyCode::Q)
+    } else {
+        return;
+    };
+    input.reset(key);
+    apply_menu_action(action, &mut state, &mut restart, &mut exit);
+}
+
+fn apply_menu_buttons(
+    mut buttons: EventReader<MenuAction>,
+    mut state: ResMut<State<GameState>>,
+    mut restart: EventWriter<RestartGame>,
+    mut exit: EventWriter<AppExit>,
+) {
+    // One per frame, the state cannot be changed twice
+    if let Some(action) = buttons.iter().next() {
+        apply_menu_action(*action, &mut state, &mut restart, &mut exit);
+    }
+}
+
+fn show_pause_menu(
+    mut egui_context: ResMut<EguiContext>,
+    mut buttons: EventWriter<MenuAction>,
+    mut audio: ResMut<AudioSettings>,
+    mut controls: ResMut<ControlsMenu>,
+    bindings: Res<KeyBindings>,
+) {
+    let mut action = None;
+    // Edited on a copy, so the settings only count as changed when a slider moves
+    let mut volumes = audio.clone();
+    egui::Area::new("pause")
+        .anchor(egui::Align2::CENTER_CENTER, (0., 0.))
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.vertical_centered(|ui| {
+                if controls.open {
+                    controls.show(ui, &bindings);
+                    return;
+                }
+                ui.colored_label(egui::Color32::WHITE, "PAUSED");
+                ui.add_space(40.);
+                if ui.button("<esc> resume").clicked() {
+                    action = Some(MenuAction::Resume);
+                }
+                let restart_key = bindings.prompt(&[Action::Restart]);
+                if ui.button(format!("{} restart", restart_key)).clicked() {
+                    action = Some(MenuAction::Restart);
+                }
+                if ui.button("controls").clicked() {
+                    controls.open = true;
+                }
+                if ui.button("<q> quit").clicked() {
+                    action = Some(MenuAction::Quit);
+                }
+                ui.add_space(20.);
+                if volumes.show(ui) {
+                    *audio = volumes.clone();
+                }
+            });
+        });
+
+    if let Some(action) = action {
+        buttons.send(action);
+    }
+}
+
+fn apply_menu_action(
+    action: MenuAction,
+    state: &mut State<GameState>,
+    restart: &mut EventWriter<RestartGame>,
+    exit: &mut EventWriter<AppExit>,
+) {
+    match action {
+        MenuAction::Resume if state.current() == &GameState::Paused => state
+            .pop()
+            .expect("State must be added to the game at this point."),
+        MenuAction::Resume => (),
+        MenuAction::Restart => restart.send(RestartGame),
+        MenuAction::Quit => exit.send(AppExit),
+    }
+}
+
+fn show_cursor_in_menus(state: Res<State<GameState>>, mut windows: ResMut<Windows>) {
+    if !state.is_changed() {
+        return;
+    }
+
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_cursor_visibility(matches!(
+            state.current(),
+            GameState::Paused | GameState::GameOver
+        ));
+    }
+}
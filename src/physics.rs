@@ -0,0 +1,156 @@
+//! Rapier, stepped once per gameplay step instead of once per frame.
+
+use bevy::{
+    ecs::schedule::{ShouldRun, Stage},
+    prelude::*,
+};
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    character::Position,
+    game::{FixedUpdateStage, GameState, FIXED_TIMESTEP},
+};
+
+/// Collision group of the player.
+pub const PLAYER_GROUP: u32 = 1 << 0;
+/// Collision group of enemies.
+pub const ENEMY_GROUP: u32 = 1 << 1;
+/// Collision group of walls, rocks and other level geometry.
+pub const WORLD_GROUP: u32 = 1 << 2;
+
+/// Stages of `PhysicsSchedule` around the rapier ones.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, StageLabel)]
+pub enum PhysicsStage {
+    /// Velocities are set from the gameplay step, before rapier picks them up.
+    Prepare,
+    /// Rapier's results are copied back into `Position`, and knockback fades.
+    Record,
+    /// Gameplay reacts to the contacts of the step, e.g. deaths and merges.
+    Resolve,
+}
+
+/// Run at the end of every `FixedUpdateStage` step, so physics sees exactly what gameplay did.
+pub struct PhysicsSchedule(pub Schedule);
+
+/// Extra velocity that fades out over time, on top of whatever the character wants to do.
+#[derive(Component, Default)]
+pub struct Knockback(pub Vec2);
+
+impl Knockback {
+    /// Fraction of the knockback kept after one second.
+    const DECAY: f32 = 0.02;
+
+    pub fn push(&mut self, velocity: Vec2) {
+        self.0 += velocity;
+    }
+}
+
+pub struct PhysicsPlugin;
+
+impl Plugin for PhysicsPlugin {
+    fn build(&self, app: &mut App) {
+        let mut schedule = Schedule::default();
+        schedule
+            .add_stage(
+                PhysicsStage::Prepare,
+                SystemStage::parallel().with_system(restore_body_transforms),
+            )
+            .add_stage(
+                PhysicsStages::SyncBackend,
+                SystemStage::parallel().with_system_set(
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsStages::SyncBackend),
+                ),
+            )
+            .add_stage(
+                PhysicsStages::StepSimulation,
+                SystemStage::parallel().with_system_set(
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsStages::StepSimulation),
+                ),
+            )
+            .add_stage(
+                PhysicsStages::Writeback,
+                SystemStage::parallel().with_system_set(
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsStages::Writeback),
+                ),
+            )
+            .add_stage(
+                PhysicsStage::Record,
+                SystemStage::parallel()
+                    .with_system(record_positions)
+                    .with_system(decay_knockback),
+            )
+            .add_stage(PhysicsStage::Resolve, SystemStage::parallel());
+
+        app.add_plugin(
+            RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0)
+                .with_default_system_setup(false),
+        )
+        .insert_resource(RapierConfiguration {
+            gravity: Vec2::ZERO,
+            timestep_mode: TimestepMode::Fixed {
+                dt: FIXED_TIMESTEP as f32,
+                substeps: 1,
+            },
+            ..default()
+        })
+        .insert_resource(PhysicsSchedule(schedule))
+        .add_system_to_stage(
+            FixedUpdateStage,
+            step_physics
+                .exclusive_system()
+                .at_end()
+                .with_run_criteria(unless_paused),
+        )
+        .add_system_set_to_stage(
+            CoreStage::Last,
+            RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsStages::DetectDespawn),
+        );
+    }
+}
+
+/// Bodies stay where they are while paused, even when contacts would push them apart.
+fn unless_paused(state: Res<State<GameState>>) -> ShouldRun {
+    if state.current() == &GameState::Paused {
+        ShouldRun::No
+    } else {
+        ShouldRun::Yes
+    }
+}
+
+fn step_physics(world: &mut World) {
+    world.resource_scope(|world, mut schedule: Mut<PhysicsSchedule>| {
+        schedule.0.run(world);
+    });
+}
+
+/// Undoes the frame interpolation, so rapier starts from where the last step left each body.
+///
+/// Fresh bodies also get their `GlobalTransform` here, before it has been propagated.
+fn restore_body_transforms(
+    mut query: Query<(&Position, &mut Transform, &mut GlobalTransform), With<RigidBody>>,
+) {
+    for (position, mut transform, mut global_transform) in &mut query {
+        if transform.translation.truncate() != position.current {
+            transform.translation.x = position.current.x;
+            transform.translation.y = position.current.y;
+        }
+        *global_transform = GlobalTransform::from(*transform);
+    }
+}
+
+fn decay_knockback(mut query: Query<&mut Knockback>) {
+    for mut knockback in &mut query {
+        if knockback.0 != Vec2::ZERO {
+            knockback.0 *= Knockback::DECAY.powf(FIXED_TIMESTEP as f32);
+            if knockback.0.length_squared() < 0.01 {
+                knockback.0 = Vec2::ZERO;
+            }
+        }
+    }
+}
+
+fn record_positions(mut query: Query<(&mut Position, &Transform), With<RigidBody>>) {
+    for (mut position, transform) in &mut query {
+        position.record(transform.translation.truncate());
+    }
+}
@@ -19,11 +19,51 @@ impl Plugin for RenderingPlugin {
     }
 }
 
-fn order_z_entities(mut query: Query<&mut Transform, With<OrderedZ>>) {
+/// Ordered sprites stay within this distance of z 0, above the floor and below the lighting mask.
+pub const ORDERED_Z_RANGE: f32 = 500.;
+
+/// Depth of a sprite at `y` seen by a camera at `camera_y`, so lower sprites are drawn in front.
+/// Sprites this far from the camera are off screen and share the ends of the range.
+pub fn ordered_z(y: f32, camera_y: f32) -> f32 {
+    (camera_y - y).clamp(-ORDERED_Z_RANGE, ORDERED_Z_RANGE)
+}
+
+fn order_z_entities(
+    mut query: Query<&mut Transform, With<OrderedZ>>,
+    camera: Query<&Transform, (With<Camera2d>, Without<OrderedZ>)>,
+) {
+    let camera_y = camera
+        .get_single()
+        .map_or(0., |camera| camera.translation.y);
     for mut transform in &mut query {
-        transform.translation.z = -transform.translation.y;
+        transform.translation.z = ordered_z(transform.translation.y, camera_y);
     }
 }
 
 #[derive(Component)]
 pub struct OrderedZ;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ordered_z_stays_in_range_far_from_the_origin() {
+        for (y, camera_y) in [
+            (-5_000., 0.),
+            (20_000., 0.),
+            (-1_000_000., -1_000_010.),
+            (1e7, -1e7),
+        ] {
+            let z = ordered_z(y, camera_y);
+            assert!(z.abs() <= ORDERED_Z_RANGE, "{} at y {}", z, y);
+        }
+    }
+
+    #[test]
+    fn lower_sprites_are_in_front_anywhere_on_screen() {
+        let camera_y = -250_000.;
+
+        assert!(ordered_z(camera_y - 20., camera_y) > ordered_z(camera_y + 10., camera_y));
+    }
+}
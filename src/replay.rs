@@ -0,0 +1,268 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::{
+    app::AppExit,
+    input::{keyboard::KeyboardInput, ButtonState, InputSystem},
+    prelude::*,
+    time::TimeSystem,
+    utils::Instant,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{game::GameState, input::KeyBindings, pause::MenuAction, rng::GameRng};
+
+/// A recorded run: the seed and key bindings it was played with and the input and time delta of
+/// every frame.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Replay {
+    pub seed: u64,
+    /// Replays recorded before keys could be rebound use the default bindings.
+    #[serde(default)]
+    pub bindings: KeyBindings,
+    pub frames: Vec<ReplayFrame>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    pub delta: Duration,
+    pub keys: Vec<RecordedKey>,
+    /// Menu buttons clicked.
+    #[serde(default)]
+    pub menu: Vec<MenuAction>,
+    /// Bindings changed on the controls screen.
+    #[serde(default)]
+    pub bindings: Option<KeyBindings>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RecordedKey {
+    pub key_code: KeyCode,
+    pub pressed: bool,
+}
+
+impl Replay {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        Ok(ron::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        fs::write(path, ron::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// Records every frame to `path`. The file is written on game over and on exit.
+///
+/// Keys are taken from `Input<KeyCode>` rather than keyboard events, leaving out keys the controls
+/// screen captured.
+pub struct InputRecorder {
+    path: PathBuf,
+    replay: Replay,
+}
+
+impl InputRecorder {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        InputRecorder {
+            path: path.into(),
+            replay: Replay::default(),
+        }
+    }
+
+    fn save(&self) {
+        match self.replay.save(&self.path) {
+            Ok(()) => info!("Saved replay to {}", self.path.display()),
+            Err(error) => error!(
+                "Could not save replay to {}: {}",
+                self.path.display(),
+                error
+            ),
+        }
+    }
+}
+
+/// Feeds a recorded run back through `Input<KeyCode>` and `Time`.
+///
+/// The resource is removed once the last frame has been played, handing control back to the keyboard.
+pub struct InputReplay {
+    replay: Replay,
+    frame: usize,
+    clock: Time,
+    now: Instant,
+}
+
+impl InputReplay {
+    pub fn new(replay: Replay) -> Self {
+        let mut clock = Time::default();
+        let now = clock.startup();
+        clock.update_with_instant(now);
+
+        InputReplay {
+            replay,
+            frame: 0,
+            clock,
+            now,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.replay.seed
+    }
+
+    fn current(&self) -> Option<&ReplayFrame> {
+        self.replay.frames.get(self.frame)
+    }
+}
+
+/// Keys are recorded after this, so systems hiding keys from the game run before it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub struct RecordInput;
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        if let Some(replay) = app.world.get_resource::<InputReplay>() {
+            let (seed, bindings) = (replay.seed(), replay.replay.bindings.clone());
+            app.insert_resource(GameRng::new(seed))
+                .insert_resource(bindings);
+        }
+
+        app.add_system_to_stage(CoreStage::First, start_recording)
+            .add_system_to_stage(CoreStage::First, replay_time.after(TimeSystem))
+            .add_system_to_stage(CoreStage::PreUpdate, replay_input.before(InputSystem))
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                record_input.label(RecordInput).after(InputSystem),
+            )
+            .add_system_to_stage(CoreStage::Last, record_menu_buttons)
+            .add_system_to_stage(CoreStage::Last, advance_replay)
+            .add_system_set(SystemSet::on_enter(GameState::GameOver).with_system(save_recording))
+            .add_system_to_stage(CoreStage::Last, save_recording_on_exit);
+    }
+}
+
+fn replay_time(replay: Option<ResMut<InputReplay>>, mut time: ResMut<Time>) {
+    if let Some(mut replay) = replay {
+        if let Some(delta) = replay.current().map(|frame| frame.delta) {
+            replay.now += delta;
+            let now = replay.now;
+            replay.clock.update_with_instant(now);
+            *time = replay.clock.clone();
+        }
+    }
+}
+
+fn replay_input(
+    replay: Option<Res<InputReplay>>,
+    mut events: ResMut<Events<KeyboardInput>>,
+    mut buttons: EventWriter<MenuAction>,
+    mut bindings: ResMut<KeyBindings>,
+) {
+    if let Some(frame) = replay.as_ref().and_then(|replay| replay.current()) {
+        if let Some(rebound) = &frame.bindings {
+            *bindings = rebound.clone();
+        }
+        buttons.send_batch(frame.menu.iter().copied());
+        events.clear();
+        for key in &frame.keys {
+            events.send(KeyboardInput {
+                scan_code: 0,
+                key_code: Some(key.key_code),
+                state: if key.pressed {
+                    ButtonState::Pressed
+                } else {
+                    ButtonState::Released
+                },
+            });
+        }
+    }
+}
+
+fn advance_replay(mut commands: Commands, replay: Option<ResMut<InputReplay>>) {
+    if let Some(mut replay) = replay {
+        replay.frame += 1;
+        if replay.current().is_none() {
+            info!("Replay finished after {} frames", replay.frame);
+            commands.remove_resource::<InputReplay>();
+        }
+    }
+}
+
+/// Takes the seed and bindings the run starts with, before any key could be rebound.
+fn start_recording(
+    recorder: Option<ResMut<InputRecorder>>,
+    rng: Res<GameRng>,
+    bindings: Res<KeyBindings>,
+) {
+    if let Some(mut recorder) = recorder {
+        if recorder.is_added() {
+            recorder.replay.seed = rng.seed();
+            recorder.replay.bindings = bindings.clone();
+        }
+    }
+}
+
+fn record_input(
+    recorder: Option<ResMut<InputRecorder>>,
+    time: Res<Time>,
+    input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+) {
+    if let Some(mut recorder) = recorder {
+        let mut keys: Vec<RecordedKey> = input
+            .get_just_released()
+            .map(|&key_code| RecordedKey {
+                key_code,
+                pressed: false,
+            })
+            .collect();
+        for &key_code in input.get_just_pressed() {
+            let press = RecordedKey {
+                key_code,
+                pressed: true,
+            };
+            // A key pressed and released within the frame is pressed first
+            if input.pressed(key_code) {
+                keys.push(press);
+            } else {
+                keys.insert(0, press);
+            }
+        }
+
+        let rebound = bindings.is_changed() && !bindings.is_added();
+        recorder.replay.frames.push(ReplayFrame {
+            delta: time.delta(),
+            keys,
+            menu: Vec::new(),
+            bindings: rebound.then(|| bindings.clone()),
+        });
+    }
+}
+
+fn record_menu_buttons(
+    recorder: Option<ResMut<InputRecorder>>,
+    mut buttons: EventReader<MenuAction>,
+) {
+    if let Some(mut recorder) = recorder {
+        if let Some(frame) = recorder.replay.frames.last_mut() {
+            frame.menu.extend(buttons.iter().copied());
+        }
+    }
+}
+
+fn save_recording(recorder: Option<Res<InputRecorder>>) {
+    if let Some(recorder) = recorder {
+        recorder.save();
+    }
+}
+
+fn save_recording_on_exit(recorder: Option<Res<InputRecorder>>, mut exit: EventReader<AppExit>) {
+    if exit.iter().count() > 0 {
+        save_recording(recorder);
+    }
+}
@@ -0,0 +1,101 @@
+use bevy::prelude::*;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::game::RestartGame;
+
+/// The single source of gameplay randomness.
+///
+/// Every round is reseeded from `seed`, so a given seed always produces the same enemy waves.
+/// `ChaCha8Rng` produces the same numbers on every platform and `rand` version, unlike `StdRng`.
+pub struct GameRng {
+    seed: u64,
+    rng: ChaCha8Rng,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        GameRng {
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn reseed(&mut self) {
+        self.rng = ChaCha8Rng::seed_from_u64(self.seed);
+    }
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        GameRng::new(rand::random())
+    }
+}
+
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+pub struct RngPlugin;
+
+impl Plugin for RngPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameRng>()
+            .add_startup_system(log_seed)
+            .add_system(reseed_on_restart);
+    }
+}
+
+fn log_seed(rng: Res<GameRng>) {
+    info!("Game seed: {}", rng.seed());
+}
+
+fn reseed_on_restart(mut events: EventReader<RestartGame>, mut rng: ResMut<GameRng>) {
+    if events.iter().count() > 0 {
+        rng.reseed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    fn draws(rng: &mut GameRng) -> Vec<u64> {
+        (0..32).map(|_| rng.gen()).collect()
+    }
+
+    #[test]
+    fn same_seed_draws_the_same_numbers() {
+        assert_eq!(draws(&mut GameRng::new(42)), draws(&mut GameRng::new(42)));
+        assert_ne!(draws(&mut GameRng::new(42)), draws(&mut GameRng::new(43)));
+    }
+
+    #[test]
+    fn reseeding_starts_over() {
+        let mut rng = GameRng::new(42);
+        let first = draws(&mut rng);
+        rng.reseed();
+
+        assert_eq!(draws(&mut rng), first);
+    }
+}
@@ -0,0 +1,182 @@
+//! Sound effects for gameplay events, panned and attenuated by where they happen relative to the
+//! player, so enemies can be heard approaching out of the dark.
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_kira_audio::prelude::*;
+
+use crate::{
+    audio::{AudioLibrary, SfxChannel},
+    character::{merge::EnemyMerged, player::PlayerPosition, Position},
+    events::{EnemyBecameDangerous, GlobalLightExtinguished, LanternToggled, PlayerDied},
+};
+
+/// Sounds closer than this play at full volume.
+const FULL_VOLUME_DISTANCE: f32 = 10.;
+/// Sounds further away than this are not heard at all.
+const HEARING_DISTANCE: f32 = 90.;
+/// Horizontal distance at which a sound is panned fully to one side.
+const PAN_DISTANCE: f32 = 60.;
+/// Quieter sounds are not played.
+const MIN_VOLUME: f64 = 0.02;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sfx {
+    LanternOn,
+    LanternOff,
+    GlobalLightOut,
+    EnemyDangerous,
+    Merge,
+    Death,
+}
+
+impl Sfx {
+    const ALL: [Sfx; 6] = [
+        Sfx::LanternOn,
+        Sfx::LanternOff,
+        Sfx::GlobalLightOut,
+        Sfx::EnemyDangerous,
+        Sfx::Merge,
+        Sfx::Death,
+    ];
+
+    fn path(self) -> &'static str {
+        match self {
+            Sfx::LanternOn => "sfx/lantern_on.wav",
+            Sfx::LanternOff => "sfx/lantern_off.wav",
+            Sfx::GlobalLightOut => "sfx/light_out.wav",
+            Sfx::EnemyDangerous => "sfx/dangerous.wav",
+            Sfx::Merge => "sfx/merge.wav",
+            Sfx::Death => "sfx/death.wav",
+        }
+    }
+
+    /// Volume right next to the player, before the sfx channel volume.
+    fn volume(self) -> f64 {
+        match self {
+            Sfx::EnemyDangerous => 0.5,
+            Sfx::Merge => 0.7,
+            Sfx::LanternOn | Sfx::LanternOff => 0.6,
+            Sfx::GlobalLightOut | Sfx::Death => 1.0,
+        }
+    }
+}
+
+/// Volume from 0 to 1 and panning from 0 (left) to 1 (right) of a sound at `source`, heard at `listener`.
+pub fn spatialize(listener: Vec2, source: Vec2) -> (f64, f64) {
+    let offset = source - listener;
+    let distance = offset.length();
+    let volume = 1.
+        - ((distance - FULL_VOLUME_DISTANCE) / (HEARING_DISTANCE - FULL_VOLUME_DISTANCE))
+            .clamp(0., 1.);
+    let panning = 0.5 + 0.5 * (offset.x / PAN_DISTANCE).clamp(-1., 1.);
+    // Squared, as loudness falls off faster than linearly
+    ((volume * volume) as f64, panning as f64)
+}
+
+struct SoundEffects(Vec<(Sfx, Handle<AudioSource>)>);
+
+/// Plays sound effects on the sfx channel, relative to the player.
+#[derive(SystemParam)]
+struct SfxPlayer<'w, 's> {
+    sounds: Res<'w, SoundEffects>,
+    channel: Res<'w, AudioChannel<SfxChannel>>,
+    asset_server: Res<'w, AssetServer>,
+    listener: Query<'w, 's, &'static Position, With<PlayerPosition>>,
+}
+
+impl<'w, 's> SfxPlayer<'w, 's> {
+    /// Plays `sfx` as heard from the player. Without a player it plays centred.
+    fn play_at(&self, sfx: Sfx, position: Vec2) {
+        let (volume, panning) = match self.listener.get_single() {
+            Ok(listener) => spatialize(listener.current, position),
+            Err(_) => (1., 0.5),
+        };
+        self.play(sfx, volume, panning);
+    }
+
+    /// Plays `sfx` centred at full volume.
+    fn play_everywhere(&self, sfx: Sfx) {
+        self.play(sfx, 1., 0.5);
+    }
+
+    fn play(&self, sfx: Sfx, volume: f64, panning: f64) {
+        let volume = volume * sfx.volume();
+        if volume < MIN_VOLUME {
+            return;
+        }
+        let source = match self.sounds.0.iter().find(|(sound, _)| *sound == sfx) {
+            Some((_, source)) => source,
+            None => return,
+        };
+        // Sounds that failed to load were already reported
+        if AudioLibrary::is_ready(&self.asset_server, source) {
+            self.channel
+                .play(source.clone())
+                .with_volume(volume)
+                .with_panning(panning);
+        }
+    }
+}
+
+pub struct SoundEffectsPlugin;
+
+impl Plugin for SoundEffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup)
+            .add_system(play_lantern_sounds)
+            .add_system(play_global_light_sound)
+            .add_system(play_enemy_sounds)
+            .add_system(play_death_sound);
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut library: ResMut<AudioLibrary>,
+) {
+    commands.insert_resource(SoundEffects(
+        Sfx::ALL
+            .into_iter()
+            .map(|sfx| (sfx, library.load(&asset_server, sfx.path())))
+            .collect(),
+    ));
+}
+
+fn play_lantern_sounds(mut events: EventReader<LanternToggled>, player: SfxPlayer) {
+    for event in events.iter() {
+        player.play_everywhere(if event.lit {
+            Sfx::LanternOn
+        } else {
+            Sfx::LanternOff
+        });
+    }
+}
+
+fn play_global_light_sound(mut events: EventReader<GlobalLightExtinguished>, player: SfxPlayer) {
+    for _ in events.iter() {
+        player.play_everywhere(Sfx::GlobalLightOut);
+    }
+}
+
+fn play_enemy_sounds(
+    mut dangerous: EventReader<EnemyBecameDangerous>,
+    mut merged: EventReader<EnemyMerged>,
+    enemies: Query<&Position>,
+    player: SfxPlayer,
+) {
+    for event in dangerous.iter() {
+        player.play_at(Sfx::EnemyDangerous, event.position);
+    }
+    for event in merged.iter() {
+        if let Ok(position) = enemies.get(event.survivor) {
+            player.play_at(Sfx::Merge, position.current);
+        }
+    }
+}
+
+fn play_death_sound(mut events: EventReader<PlayerDied>, player: SfxPlayer) {
+    for _ in events.iter() {
+        player.play_everywhere(Sfx::Death);
+    }
+}
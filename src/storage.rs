@@ -0,0 +1,70 @@
+//! Small key-value persistence: one RON file per key on native, `localStorage` on the web.
+
+use bevy::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+
+pub fn load<T: DeserializeOwned>(key: &str) -> Option<T> {
+    let value = read(key)?;
+    match ron::from_str(&value) {
+        Ok(value) => Some(value),
+        Err(error) => {
+            error!("Could not parse stored {}: {}", key, error);
+            None
+        }
+    }
+}
+
+pub fn save<T: Serialize>(key: &str, value: &T) {
+    match ron::to_string(value) {
+        Ok(value) => write(key, &value),
+        Err(error) => error!("Could not serialize {}: {}", key, error),
+    }
+}
+
+/// Seconds since the unix epoch.
+pub fn now() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        (js_sys::Date::now() / 1000.0) as u64
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read(key: &str) -> Option<String> {
+    std::fs::read_to_string(format!("{}.ron", key)).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write(key: &str, value: &str) {
+    if let Err(error) = std::fs::write(format!("{}.ron", key), value) {
+        error!("Could not write {}: {}", key, error);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read(key: &str) -> Option<String> {
+    local_storage()?
+        .get_item(&format!("contour.{}", key))
+        .ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write(key: &str, value: &str) {
+    let stored = local_storage()
+        .map(|storage| storage.set_item(&format!("contour.{}", key), value).is_ok())
+        .unwrap_or(false);
+    if !stored {
+        error!("Could not write {} to local storage", key);
+    }
+}
@@ -6,7 +6,12 @@ use crate::{
         player::{Lantern, ScreenTextTimer},
         GameOver,
     },
-    game::GameState,
+    config::GameConfig,
+    events::{PlayerDied, ScoreMilestone},
+    game::{fixed_timestep, FixedUpdateStage, GameState, RestartGame, FIXED_TIMESTEP},
+    input::{Action, KeyBindings},
+    pause::{ApplyMenuButtons, MenuAction},
+    rng::GameRng,
 };
 
 #[derive(Component)]
@@ -20,7 +25,8 @@ pub struct SubText(pub String);
 pub struct ExpositionText {
     pub shown: bool,
     pub main: &'static str,
-    pub sub: &'static str,
+    /// Actions whose keys are prompted for below the text.
+    pub keys: &'static [Action],
 }
 pub struct ExpositionTexts {
     pub inner: [ExpositionText; 4],
@@ -30,22 +36,22 @@ const EXPOSITION_TEXTS: [ExpositionText; 4] = [
     ExpositionText {
         shown: false,
         main: "CONTOUR",
-        sub: "<space>",
+        keys: &[Action::Confirm],
     },
     ExpositionText {
         shown: false,
         main: "They are stronger together, and that is meant literally.",
-        sub: "<g>",
+        keys: &[Action::KillGlobalLight],
     },
     ExpositionText {
         shown: false,
         main: "Your light will slow them down.",
-        sub: "<f>",
+        keys: &[Action::ToggleLantern],
     },
     ExpositionText {
         shown: false,
         main: "But in time, they will consume you.",
-        sub: "<wasd>",
+        keys: &Action::MOVE,
     },
 ];
 
@@ -63,23 +69,53 @@ impl Plugin for TextPlugin {
                 .with_system(step_through_prelude),
         )
         .add_system_set(
+            SystemSet::on_update(GameState::InGame).with_system(start_screen_text_timer),
+        )
+        .add_system_set_to_stage(
+            FixedUpdateStage,
             SystemSet::on_update(GameState::InGame)
-                .with_system(start_screen_text_timer)
                 .with_system(clear_screen_text)
-                .with_system(update_score_by_time)
-                .with_system(update_score_text),
+                .with_system(update_score_by_time),
         )
-        .add_system(show_center_text);
+        .add_system_set(SystemSet::on_update(GameState::GameOver).with_system(restart_on_input))
+        .add_system(reset_text)
+        .add_system(show_game_over_text);
     }
 }
 
-fn setup(
+pub struct TextDisplayPlugin;
+
+impl Plugin for TextDisplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MilestoneText>()
+            .add_startup_system(setup_display)
+            .add_system_to_stage(CoreStage::PostUpdate, insert_score_text)
+            .add_system_set(SystemSet::on_update(GameState::InGame).with_system(update_score_text))
+            .add_system(show_center_text.before(ApplyMenuButtons))
+            .add_system(show_milestone_text);
+    }
+}
+
+const MILESTONE_SECONDS: f32 = 2.0;
+
+/// The last score milestone and how long it is still shown, apart from the centre text.
+#[derive(Default)]
+struct MilestoneText(Option<(u32, Timer)>);
+
+fn setup(mut commands: Commands) {
+    commands
+        .spawn()
+        .insert(Name::new("Score"))
+        .insert(Score(0.0));
+}
+
+fn insert_score_text(
     mut commands: Commands,
+    query: Query<Entity, Added<Score>>,
     asset_server: Res<AssetServer>,
-    mut egui_context: ResMut<EguiContext>,
 ) {
-    commands
-        .spawn_bundle(
+    for entity in &query {
+        commands.entity(entity).insert_bundle(
             TextBundle::from_sections([TextSection::from_style(TextStyle {
                 font: asset_server.load("PressStart2P.ttf"),
                 font_size: 40.0,
@@ -96,9 +132,11 @@ fn setup(
                 ..default()
             })
             .with_text_alignment(TextAlignment::TOP_LEFT),
-        )
-        .insert(Score(0.0));
+        );
+    }
+}
 
+fn setup_display(mut egui_context: ResMut<EguiContext>) {
     let mut fonts = egui::FontDefinitions::default();
     fonts.font_data.insert(
         "press_start".to_owned(),
@@ -117,6 +155,8 @@ fn setup(
 fn show_center_text(
     state: Res<State<GameState>>,
     mut egui_context: ResMut<EguiContext>,
+    mut buttons: EventWriter<MenuAction>,
+    bindings: Res<KeyBindings>,
     query: Query<(&MainText, &SubText)>,
 ) {
     for (line1, line2) in &query {
@@ -138,9 +178,14 @@ fn show_center_text(
             .anchor(egui::Align2::CENTER_CENTER, (0., 50.))
             .show(egui_context.ctx_mut(), |ui| match state.current() {
                 GameState::GameOver => {
-                    ui.colored_label(egui::Color32::WHITE, "<refresh page to restart>".to_owned())
+                    let restart_key = bindings.prompt(&[Action::Restart]);
+                    if ui.button(format!("{} restart", restart_key)).clicked() {
+                        buttons.send(MenuAction::Restart);
+                    }
+                }
+                _ => {
+                    ui.colored_label(egui::Color32::WHITE, line2.0.to_owned());
                 }
-                _ => ui.colored_label(egui::Color32::WHITE, line2.0.to_owned()),
             });
     }
 }
@@ -152,17 +197,14 @@ fn start_screen_text_timer(mut query: Query<&mut ScreenTextTimer, Changed<MainTe
     }
 }
 
-fn clear_screen_text(
-    time: Res<Time>,
-    mut query: Query<(&mut MainText, &mut SubText, &mut ScreenTextTimer)>,
-) {
+fn clear_screen_text(mut query: Query<(&mut MainText, &mut SubText, &mut ScreenTextTimer)>) {
     for (mut main, mut sub, mut timer) in &mut query {
         if timer.0.just_finished() {
             main.0 = "".to_owned();
             sub.0 = "".to_owned();
             timer.0.reset();
         } else {
-            timer.0.tick(time.delta());
+            timer.0.tick(fixed_timestep());
         }
     }
 }
@@ -170,20 +212,80 @@ fn clear_screen_text(
 fn update_score_by_time(
     mut query: Query<&mut Score>,
     game_over_query: Query<(&Lantern, &GameOver)>,
-    time: Res<Time>,
+    config: Res<GameConfig>,
+    mut milestones: EventWriter<ScoreMilestone>,
 ) {
     for mut score in &mut query {
         if let Ok((lantern, game_over)) = game_over_query.get_single() {
             if !game_over.0 {
+                let previous = score.0;
                 score.0 += match lantern.0 {
-                    true => time.delta_seconds(),
-                    false => time.delta_seconds() * 2.0,
+                    true => FIXED_TIMESTEP as f32,
+                    false => FIXED_TIMESTEP as f32 * config.dark_score_multiplier,
+                };
+
+                let milestone = (score.0 / config.score_milestone).floor();
+                if milestone > (previous / config.score_milestone).floor() {
+                    milestones.send(ScoreMilestone {
+                        score: (milestone * config.score_milestone) as u32,
+                    });
                 }
             }
         }
     }
 }
 
+fn show_game_over_text(
+    mut died: EventReader<PlayerDied>,
+    rng: Res<GameRng>,
+    mut query: Query<(&mut MainText, &mut SubText)>,
+) {
+    for event in died.iter() {
+        if let Ok((mut main, mut sub)) = query.get_mut(event.player) {
+            main.0 = "GAME OVER".to_owned();
+            sub.0 = format!("Score: {}\nSeed: {}", event.score.round(), rng.seed());
+        }
+    }
+}
+
+fn show_milestone_text(
+    time: Res<Time>,
+    state: Res<State<GameState>>,
+    mut milestones: EventReader<ScoreMilestone>,
+    mut milestone: ResMut<MilestoneText>,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    if let Some(event) = milestones.iter().last() {
+        milestone.0 = Some((event.score, Timer::from_seconds(MILESTONE_SECONDS, false)));
+    }
+    match state.current() {
+        GameState::InGame => (),
+        // Shown again once resumed
+        GameState::Paused => return,
+        GameState::Prelude | GameState::GameOver => {
+            milestone.0 = None;
+            return;
+        }
+    }
+
+    let shown = milestone
+        .0
+        .as_mut()
+        .and_then(|(score, timer)| (!timer.tick(time.delta()).finished()).then(|| *score));
+    let score = match shown {
+        Some(score) => score,
+        None => {
+            milestone.0 = None;
+            return;
+        }
+    };
+    egui::Area::new("milestone")
+        .anchor(egui::Align2::CENTER_TOP, (0., 40.))
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.colored_label(egui::Color32::GOLD, score.to_string());
+        });
+}
+
 fn update_score_text(mut query: Query<(&mut Text, &Score)>) {
     for (mut text, score) in &mut query {
         text.sections[0].value = format!("{}", score.0.round());
@@ -192,6 +294,7 @@ fn update_score_text(mut query: Query<(&mut Text, &Score)>) {
 
 fn show_exposition_texts(
     texts: ResMut<ExpositionTexts>,
+    bindings: Res<KeyBindings>,
     mut query: Query<(&mut MainText, &mut SubText)>,
 ) {
     for (mut main, mut sub) in &mut query {
@@ -199,7 +302,7 @@ fn show_exposition_texts(
         match next_unshown_text {
             Some(text) => {
                 main.0 = text.main.to_owned();
-                sub.0 = text.sub.to_owned();
+                sub.0 = bindings.prompt(text.keys);
             }
             None => (),
         }
@@ -208,36 +311,61 @@ fn show_exposition_texts(
 
 fn step_through_prelude(
     mut state: ResMut<State<GameState>>,
-    mut local: Local<usize>,
     input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
     mut texts: ResMut<ExpositionTexts>,
 ) {
-    match *local {
-        0 => {
-            if input.just_pressed(KeyCode::Space) {
-                *local = 1;
+    match texts.inner.iter().position(|text| !text.shown) {
+        Some(0) => {
+            if bindings.just_pressed(&input, Action::Confirm) {
                 texts.inner[0].shown = true;
             }
         }
-        1 => {
-            if input.just_pressed(KeyCode::G) {
-                *local = 2;
+        Some(1) => {
+            if bindings.just_pressed(&input, Action::KillGlobalLight) {
                 texts.inner[1].shown = true;
             }
         }
-        2 => {
-            if input.just_pressed(KeyCode::F) {
-                *local = 3;
+        Some(2) => {
+            if bindings.just_pressed(&input, Action::ToggleLantern) {
                 texts.inner[2].shown = true;
             }
         }
-        _ => {
-            if input.any_pressed([KeyCode::W, KeyCode::A, KeyCode::S, KeyCode::D]) {
+        Some(_) => {
+            if bindings.any_pressed(&input, &Action::MOVE) {
                 state
                     .set(GameState::InGame)
                     .expect("State must be added to the game at this point.");
                 texts.inner[3].shown = true;
             }
         }
+        None => (),
+    }
+}
+
+fn restart_on_input(
+    input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut restart: EventWriter<RestartGame>,
+) {
+    if bindings.just_pressed(&input, Action::Restart) {
+        restart.send(RestartGame);
+    }
+}
+
+fn reset_text(
+    mut events: EventReader<RestartGame>,
+    mut texts: ResMut<ExpositionTexts>,
+    mut query: Query<&mut Score>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    // The title card is only shown on the first run.
+    texts.inner = EXPOSITION_TEXTS;
+    texts.inner[0].shown = true;
+    for mut score in &mut query {
+        score.0 = 0.0;
     }
 }
@@ -0,0 +1,90 @@
+//! Drives the headless game from tests.
+
+#![allow(dead_code)]
+
+use bevy::{
+    asset::AssetServerSettings,
+    input::{keyboard::KeyboardInput, ButtonState},
+    prelude::*,
+    time::TimeSystem,
+    utils::Instant,
+};
+use contour::{game::fixed_timestep, ContourHeadlessPlugins, GameState};
+
+/// The headless game. `setup` inserts resources the plugins read while they are built.
+pub fn headless_app(setup: impl FnOnce(&mut App)) -> App {
+    let mut app = App::new();
+    // Without assets every run plays the built-in config, archetypes and arena, so nothing depends
+    // on when a file finishes loading.
+    app.insert_resource(AssetServerSettings {
+        asset_folder: "tests/no-assets".to_owned(),
+        ..default()
+    });
+    setup(&mut app);
+    app.add_plugins(ContourHeadlessPlugins);
+    app
+}
+
+/// Advances `Time` by exactly one gameplay step every frame, however fast the test runs.
+pub fn step_time(app: &mut App) {
+    let mut clock = Time::default();
+    let now = clock.startup();
+    clock.update_with_instant(now);
+    app.insert_resource(SteppedClock { clock, now })
+        .add_system_to_stage(CoreStage::First, step_clock.after(TimeSystem));
+}
+
+struct SteppedClock {
+    clock: Time,
+    now: Instant,
+}
+
+fn step_clock(mut stepped: ResMut<SteppedClock>, mut time: ResMut<Time>) {
+    stepped.now += fixed_timestep();
+    let now = stepped.now;
+    stepped.clock.update_with_instant(now);
+    *time = stepped.clock.clone();
+}
+
+pub fn run(app: &mut App, frames: usize) {
+    for _ in 0..frames {
+        app.update();
+    }
+}
+
+pub fn press(app: &mut App, key: KeyCode) {
+    send_key(app, key, ButtonState::Pressed);
+}
+
+pub fn release(app: &mut App, key: KeyCode) {
+    send_key(app, key, ButtonState::Released);
+}
+
+/// Presses `key` for one frame.
+pub fn tap(app: &mut App, key: KeyCode) {
+    press(app, key);
+    app.update();
+    release(app, key);
+    app.update();
+}
+
+fn send_key(app: &mut App, key: KeyCode, state: ButtonState) {
+    app.world.send_event(KeyboardInput {
+        scan_code: 0,
+        key_code: Some(key),
+        state,
+    });
+}
+
+/// Steps through the prelude with the default bindings, leaving the player walking right.
+pub fn start_round(app: &mut App) {
+    for key in [KeyCode::Space, KeyCode::G, KeyCode::F] {
+        tap(app, key);
+    }
+    press(app, KeyCode::D);
+    app.update();
+}
+
+pub fn state(app: &App) -> GameState {
+    app.world.resource::<State<GameState>>().current().clone()
+}
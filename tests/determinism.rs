@@ -0,0 +1,33 @@
+mod common;
+
+use bevy::prelude::*;
+use contour::{events::EnemySpawned, rng::GameRng};
+
+use common::{headless_app, run, start_round, step_time};
+
+/// Where the first enemies of a round spawn, walking right for `frames` with `seed`.
+fn spawns(seed: u64, frames: usize) -> Vec<Vec2> {
+    let mut app = headless_app(|app| {
+        app.insert_resource(GameRng::new(seed));
+    });
+    step_time(&mut app);
+    run(&mut app, 2);
+    start_round(&mut app);
+
+    let mut reader = app.world.resource::<Events<EnemySpawned>>().get_reader();
+    let mut positions = Vec::new();
+    for _ in 0..frames {
+        app.update();
+        let events = app.world.resource::<Events<EnemySpawned>>();
+        positions.extend(reader.iter(events).map(|event| event.position));
+    }
+    positions
+}
+
+#[test]
+fn same_seed_spawns_the_same_enemies() {
+    let first = spawns(3, 600);
+
+    assert!(!first.is_empty());
+    assert_eq!(spawns(3, 600), first);
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use contour::{character::Position, GameState};
+
+use common::{headless_app, press, run, start_round, state, step_time, tap};
+
+#[test]
+fn keys_step_through_the_prelude_into_the_game() {
+    let mut app = headless_app(|_| ());
+    run(&mut app, 2);
+    assert_eq!(state(&app), GameState::Prelude);
+
+    // Title card, global light and lantern
+    for key in [KeyCode::Space, KeyCode::G, KeyCode::F] {
+        tap(&mut app, key);
+        assert_eq!(state(&app), GameState::Prelude);
+    }
+
+    press(&mut app, KeyCode::D);
+    run(&mut app, 2);
+    assert_eq!(state(&app), GameState::InGame);
+}
+
+#[test]
+fn bodies_stay_put_while_paused() {
+    let mut app = headless_app(step_time);
+    start_round(&mut app);
+    let body = app
+        .world
+        .spawn()
+        .insert_bundle(TransformBundle::from_transform(Transform::from_xyz(
+            500., 500., 0.,
+        )))
+        .insert(Position::new(Vec2::new(500., 500.)))
+        .insert(RigidBody::Dynamic)
+        .insert(Collider::ball(1.))
+        .insert(Velocity::linear(Vec2::X * 10.))
+        .id();
+    let position = |app: &App| app.world.get::<Position>(body).unwrap().current;
+
+    tap(&mut app, KeyCode::Escape);
+    assert_eq!(state(&app), GameState::Paused);
+    let paused_at = position(&app);
+    run(&mut app, 10);
+    assert_eq!(position(&app), paused_at);
+
+    tap(&mut app, KeyCode::Escape);
+    assert_eq!(state(&app), GameState::InGame);
+    run(&mut app, 10);
+    assert!(position(&app).x > paused_at.x);
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use bevy::{app::AppExit, prelude::*};
+use contour::{
+    character::{player::PlayerPosition, Position},
+    pause::MenuAction,
+    replay::{InputRecorder, InputReplay, Replay},
+    rng::GameRng,
+    text::Score,
+    GameState,
+};
+
+use common::{headless_app, press, release, run, start_round, state, step_time, tap};
+
+fn score(app: &mut App) -> f32 {
+    app.world.query::<&Score>().single(&app.world).0
+}
+
+fn player_position(app: &mut App) -> Vec2 {
+    app.world
+        .query_filtered::<&Position, With<PlayerPosition>>()
+        .single(&app.world)
+        .current
+}
+
+#[test]
+fn replay_plays_the_recorded_run_again() {
+    let path = std::env::temp_dir().join(format!("contour-replay-{}.ron", std::process::id()));
+
+    let mut recording = headless_app(|app| {
+        app.insert_resource(GameRng::new(7))
+            .insert_resource(InputRecorder::new(&path));
+    });
+    step_time(&mut recording);
+    run(&mut recording, 2);
+
+    start_round(&mut recording);
+    run(&mut recording, 90);
+    release(&mut recording, KeyCode::D);
+    // Paused with the key, restarted with the button
+    tap(&mut recording, KeyCode::Escape);
+    assert_eq!(state(&recording), GameState::Paused);
+    recording.world.send_event(MenuAction::Restart);
+    run(&mut recording, 3);
+    assert_eq!(state(&recording), GameState::Prelude);
+
+    start_round(&mut recording);
+    press(&mut recording, KeyCode::W);
+    run(&mut recording, 60);
+    // Paused with the key, resumed with the button
+    tap(&mut recording, KeyCode::Escape);
+    recording.world.send_event(MenuAction::Resume);
+    run(&mut recording, 2);
+    assert_eq!(state(&recording), GameState::InGame);
+    run(&mut recording, 120);
+    release(&mut recording, KeyCode::D);
+    release(&mut recording, KeyCode::W);
+    recording.world.send_event(AppExit);
+    recording.update();
+
+    let expected = (score(&mut recording), player_position(&mut recording));
+    assert!(expected.0 > 0.);
+
+    let replay = Replay::load(&path).expect("The recording was saved on exit.");
+    std::fs::remove_file(&path).ok();
+    let mut replaying = headless_app(|app| {
+        app.insert_resource(InputReplay::new(replay));
+    });
+    while replaying.world.contains_resource::<InputReplay>() {
+        replaying.update();
+    }
+
+    assert_eq!(
+        (score(&mut replaying), player_position(&mut replaying)),
+        expected
+    );
+}